@@ -1,14 +1,19 @@
 #![feature(let_chains)]
 
 pub mod msg;
+pub mod project;
 pub mod script;
 
 use std::env;
 use std::fs::OpenOptions;
 use std::io::{Read, Write};
+use std::path::Path;
 use std::process::exit;
+use crate::project::Manifest;
+use crate::script::cache;
 use crate::script::assembly::assembler::assemble;
 use crate::script::assembly::linker::{AssemblyFile, link};
+use crate::script::assembly::object::Library;
 use crate::script::compiler::codegen::Generator;
 use crate::script::compiler::lexer::Lexer;
 use crate::script::compiler::parser::Parser;
@@ -21,26 +26,50 @@ fn main() {
     }
 
     match args[1].as_str() {
+        "build" => project::build(),
+        "clean" => project::clean(),
         "-c" | "--compile" => {
             if args.len() < 3 {
                 return;
             }
             let mut paths = Vec::new();
-            let mut output = "out.mv".to_string();
+            let mut output = None;
+            let mut include = Vec::new();
+            let mut optimize = false;
+            let mut lib = false;
             let mut o = false;
+            let mut i = false;
             for arg in &args[2..] {
                 if o {
-                    output = arg.clone();
-                    break;
+                    output = Some(arg.clone());
+                    o = false;
+                    continue;
+                }
+                if i {
+                    include.push(arg.clone());
+                    i = false;
+                    continue;
                 }
                 if arg == "--output" || arg == "-o" {
                     o = true;
                 }
+                else if arg == "--include" || arg == "-I" {
+                    i = true;
+                }
+                else if let Some(path) = arg.strip_prefix("-I") {
+                    include.push(path.to_string());
+                }
+                else if arg == "--optimize" || arg == "-O" {
+                    optimize = true;
+                }
+                else if arg == "--lib" {
+                    lib = true;
+                }
                 else {
                     paths.push(arg.clone());
                 }
             }
-            compile(paths, output)
+            compile(paths, output, optimize, include, lib)
         }
         _ => {
             execute(args[1].clone(), env::args().skip(2).collect());
@@ -52,43 +81,68 @@ fn main() {
     //test_assembler();
 }
 
-fn compile(paths: Vec<String>, output: String) {
-    let asm = paths.into_iter().map(|path| {
-        let mut file = OpenOptions::new().read(true).open(&path).unwrap();
-        let mut code = String::new();
-        file.read_to_string(&mut code).unwrap();
-
-        let lexer = Lexer::new(code);
-
-        let parser = Parser::new(lexer);
+fn compile(paths: Vec<String>, output: Option<String>, optimize: bool, mut include: Vec<String>, lib: bool) {
+    let asm = paths.into_iter().map(|path| compile_file(&path, optimize)).collect::<Vec<_>>();
+
+    if lib {
+        let single = asm.len() == 1;
+        for file in asm {
+            let path = match &output {
+                Some(output) if single => output.clone(),
+                _ => format!("{}.mvl", file.name)
+            };
+            let library = Library::new(file.name, &file.path, &file.code).unwrap_or_else(|e| {
+                eprintln!("Failed to create library: {}", e);
+                exit(1);
+            });
+            let mut file = OpenOptions::new().create(true).write(true).truncate(true).open(path).unwrap();
+            file.write_all(&library.to_bytes()).unwrap();
+        }
+        return;
+    }
 
-        let result = parser.parse();
+    if let Some((root, manifest)) = Manifest::find(Path::new(".")) {
+        include.extend(manifest.lib.iter().map(|l| root.join(l).to_string_lossy().to_string()));
+    }
 
-        if let Err(e) = result {
-            println!("{:?}", e);
-            exit(1);
-        }
-        let result = result.unwrap();
+    let linked = link(asm, &include, optimize);
 
-        let generator = Generator::new(result);
+    let bytecode = assemble(linked);
+    let output = output.unwrap_or("out.mv".to_string());
+    let mut file = OpenOptions::new().create(true).write(true).truncate(true).open(output).unwrap();
+    file.write_all(&bytecode).unwrap();
+}
 
-        let script = generator.generate();
+pub fn compile_file(path: &str, optimize: bool) -> AssemblyFile {
+    let mut file = OpenOptions::new().read(true).open(path).unwrap_or_else(|e| {
+        eprintln!("Failed to open {}: {}", path, e);
+        exit(1);
+    });
+    let mut code = String::new();
+    file.read_to_string(&mut code).unwrap();
 
-        let mut parts = path.split('/').last().unwrap().split('.').collect::<Vec<_>>();
-        parts.pop();
-        let name = parts.join(".");
+    let mut parts = path.split('/').last().unwrap().split('.').collect::<Vec<_>>();
+    parts.pop();
+    let name = parts.join(".");
+    let dir = Path::new(path).parent().map(|p| p.to_string_lossy().to_string()).unwrap_or_default();
 
-        AssemblyFile {
+    if path.ends_with(".masm") {
+        return AssemblyFile {
             name,
-            code: script
-        }
-    }).collect();
+            dir,
+            path: path.to_string(),
+            code
+        };
+    }
 
-    let linked = link(asm);
+    let script = cache::compile(code, optimize);
 
-    let bytecode = assemble(linked);
-    let mut file = OpenOptions::new().create(true).write(true).truncate(true).open(output).unwrap();
-    file.write_all(&bytecode).unwrap();
+    AssemblyFile {
+        name,
+        dir,
+        path: path.to_string(),
+        code: script
+    }
 }
 
 fn execute(path: String, args: Vec<String>) {
@@ -104,7 +158,7 @@ fn execute(path: String, args: Vec<String>) {
     }).next().unwrap();
     let mut bytecode = Vec::new();
     file.read_to_end(&mut bytecode).expect("Failed to read file");
-    run(&bytecode, args);
+    exit(run(&bytecode, args));
 }
 
 fn test_compiler() {
@@ -130,6 +184,8 @@ fn test_compiler() {
 
     let script = AssemblyFile {
         name: "script".to_string(),
+        dir: String::new(),
+        path: "mvscript/script.mvs".to_string(),
         code: script
     };
 
@@ -155,10 +211,12 @@ fn test_compiler() {
 
     let git = AssemblyFile {
         name: "git".to_string(),
+        dir: String::new(),
+        path: "mvscript/git.mvs".to_string(),
         code: git
     };
 
-    let linked = link(vec![script]);
+    let linked = link(vec![script], &[], false);
 
     let bytecode = assemble(linked);
     let mut file = OpenOptions::new().create(true).write(true).truncate(true).open("out.mv").unwrap();
@@ -176,15 +234,19 @@ fn test_assembler() {
 
     let test = AssemblyFile {
         name: "test.masm".to_string(),
+        dir: String::new(),
+        path: "masm/test.masm".to_string(),
         code: assembly
     };
 
     let lib = AssemblyFile {
         name: "git.masm".to_string(),
+        dir: String::new(),
+        path: "masm/git.masm".to_string(),
         code: git
     };
 
-    let assembly = link(vec![test]);
+    let assembly = link(vec![test], &[], false);
 
     println!("{}", assembly);
 
@@ -0,0 +1,159 @@
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use std::time::SystemTime;
+use serde::Deserialize;
+use crate::compile_file;
+use crate::script::assembly::assembler::assemble;
+use crate::script::assembly::linker::link_objects;
+use crate::script::assembly::object::Library;
+use crate::script::cache;
+
+pub const MANIFEST: &str = "mvc.toml";
+
+fn err(str: String) -> ! {
+    eprintln!("{}", str);
+    exit(1);
+}
+
+/// The `mvc.toml` project manifest. All paths are relative to the directory containing the manifest.
+#[derive(Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Manifest {
+    pub name: String,
+    pub entry: String,
+    pub src: Vec<String>,
+    pub lib: Vec<String>,
+    pub output: Option<String>,
+    pub opt_level: u8,
+    pub target: String,
+}
+
+impl Default for Manifest {
+    fn default() -> Self {
+        Self {
+            name: "out".to_string(),
+            entry: "src/main.mvs".to_string(),
+            src: vec!["src".to_string()],
+            lib: Vec::new(),
+            output: None,
+            opt_level: 0,
+            target: "target".to_string(),
+        }
+    }
+}
+
+impl Manifest {
+    /// Looks for a manifest in `dir` and its parents, returning the project root together with the manifest.
+    pub fn find(dir: &Path) -> Option<(PathBuf, Manifest)> {
+        let dir = dir.canonicalize().ok()?;
+        let root = dir.ancestors().find(|d| d.join(MANIFEST).is_file())?;
+        Some((root.to_path_buf(), Self::load(&root.join(MANIFEST))))
+    }
+
+    pub fn load(path: &Path) -> Manifest {
+        let content = fs::read_to_string(path).unwrap_or_else(|e| err(format!("Failed to read {}: {}", path.display(), e)));
+        toml::from_str(&content).unwrap_or_else(|e| err(format!("Invalid manifest {}: {}", path.display(), e)))
+    }
+}
+
+/// Builds the project described by the nearest `mvc.toml`. Every source module is compiled into
+/// `<target>/obj` as a library object, skipping modules whose object is newer than both the source
+/// and the manifest, and the entry module is then linked against them into `<target>/<output>`.
+pub fn build() {
+    let Some((root, manifest)) = Manifest::find(Path::new(".")) else {
+        err(format!("No {} found in the current directory or any parent directory!", MANIFEST));
+    };
+    let target = root.join(&manifest.target);
+    let obj = target.join("obj");
+    let optimize = manifest.opt_level > 0;
+    let manifest_time = modified(&root.join(MANIFEST));
+
+    let entry = root.join(&manifest.entry);
+    if !entry.is_file() {
+        err(format!("Entry module {} does not exist!", entry.display()));
+    }
+    let entry = entry.canonicalize().unwrap_or_else(|e| err(format!("Failed to resolve {}: {}", entry.display(), e)));
+    let mut sources = vec![(module_name(&entry, entry.parent().unwrap()), entry.clone())];
+    for src in &manifest.src {
+        let dir = root.join(src);
+        for path in discover(&dir) {
+            if path.canonicalize().ok().as_ref() != Some(&entry) {
+                sources.push((module_name(&path, &dir), path));
+            }
+        }
+    }
+
+    let mut compiled = 0;
+    for (name, path) in &sources {
+        let object = obj.join(format!("{}.mvl", name));
+        let object_time = modified(&object);
+        if object_time.is_some() && object_time > modified(path) && object_time > manifest_time {
+            continue;
+        }
+        let file = compile_file(&path.to_string_lossy(), optimize);
+        let library = Library::new(name.clone(), &file.path, &file.code).unwrap_or_else(|e| err(format!("Failed to create library: {}", e)));
+        if let Some(parent) = object.parent() {
+            fs::create_dir_all(parent).unwrap_or_else(|e| err(format!("Failed to create {}: {}", parent.display(), e)));
+        }
+        fs::write(&object, library.to_bytes()).unwrap_or_else(|e| err(format!("Failed to write {}: {}", object.display(), e)));
+        compiled += 1;
+    }
+    println!("Compiled {} of {} modules", compiled, sources.len());
+
+    let object = obj.join(format!("{}.mvl", sources[0].0));
+    let bytes = fs::read(&object).unwrap_or_else(|e| err(format!("Failed to read {}: {}", object.display(), e)));
+    let entry = Library::from_bytes(&bytes).unwrap_or_else(|e| err(format!("Failed to load library {}: {}", object.display(), e)));
+    let include = manifest.lib.iter().map(|l| root.join(l).to_string_lossy().to_string()).collect::<Vec<_>>();
+
+    let linked = link_objects(vec![(entry, obj.to_string_lossy().to_string())], &include, optimize);
+    let bytecode = assemble(linked);
+
+    let output = target.join(manifest.output.clone().unwrap_or(format!("{}.mv", manifest.name)));
+    let mut file = OpenOptions::new().create(true).write(true).truncate(true).open(&output).unwrap_or_else(|e| err(format!("Failed to write {}: {}", output.display(), e)));
+    file.write_all(&bytecode).unwrap();
+    println!("Built {}", output.display());
+}
+
+/// Removes the compilation cache and, inside a project, its target directory.
+pub fn clean() {
+    cache::clean().unwrap_or_else(|e| err(format!("Failed to remove cache {}: {}", cache::dir().display(), e)));
+    println!("Removed {}", cache::dir().display());
+    if let Some((root, manifest)) = Manifest::find(Path::new(".")) {
+        let target = root.join(&manifest.target);
+        if target.exists() {
+            fs::remove_dir_all(&target).unwrap_or_else(|e| err(format!("Failed to remove {}: {}", target.display(), e)));
+            println!("Removed {}", target.display());
+        }
+    }
+}
+
+fn discover(dir: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let Ok(entries) = fs::read_dir(dir) else {
+        err(format!("Source directory {} does not exist!", dir.display()));
+    };
+    let mut entries = entries.flatten().map(|e| e.path()).collect::<Vec<_>>();
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            files.extend(discover(&path));
+        }
+        else if path.extension().is_some_and(|e| e == "mvs" || e == "masm") {
+            files.push(path);
+        }
+    }
+    files
+}
+
+/// The module name is the path relative to its source directory without the extension, like `util/strings`.
+fn module_name(path: &Path, dir: &Path) -> String {
+    let relative = path.strip_prefix(dir).unwrap_or(path).with_extension("");
+    relative.to_string_lossy().replace('\\', "/")
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
@@ -1,42 +1,149 @@
-use hashbrown::HashMap;
+use std::cell::RefCell;
+use std::process::exit;
+use std::str::FromStr;
+use hashbrown::{HashMap, HashSet};
 use bytebuffer::ByteBuffer;
 use mvutils::save::{Loader, Saver};
-use mvutils::utils::format_escaped;
 use crate::script::assembly::consts::*;
-use crate::script::utils::parse_char;
+use crate::script::assembly::lexer::{lex, position, Line, Locator, Token};
 
+thread_local! {
+    static LOCATION: RefCell<(String, usize)> = const { RefCell::new((String::new(), 0)) };
+    static ERRORS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Records an error at the current location. Assembling continues after an error, so that all of them
+/// can be reported at once by `report`.
 fn err(str: String) {
-    eprintln!("{}", str);
-    std::process::exit(1);
+    let message = LOCATION.with_borrow(|(file, line)| match (file.is_empty(), *line) {
+        (_, 0) => str,
+        (true, line) => format!("line {}: {}", line, str),
+        (false, line) => format!("{}: {}", position(file, line), str),
+    });
+    ERRORS.with_borrow_mut(|e| e.push(message));
+}
+
+fn err_at(location: &(String, usize), str: String) {
+    LOCATION.set(location.clone());
+    err(str);
+}
+
+/// Prints all recorded errors and exits if there were any.
+fn report() {
+    let errors = ERRORS.take();
+    if !errors.is_empty() {
+        for error in &errors {
+            eprintln!("{}", error);
+        }
+        eprintln!("Assembling failed with {} error{}", errors.len(), if errors.len() == 1 { "" } else { "s" });
+        exit(1);
+    }
+}
+
+fn location() -> (String, usize) {
+    LOCATION.with_borrow(|l| l.clone())
+}
+
+fn set_location(file: &str, line: usize) {
+    LOCATION.set((file.to_string(), line));
+}
+
+fn parse<T: FromStr + Default>(token: &str, kind: &str) -> T {
+    token.parse().unwrap_or_else(|_| {
+        err(format!("Invalid {}: \"{}\"", kind, token));
+        T::default()
+    })
+}
+
+fn is_ident(token: &str) -> bool {
+    token.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+}
+
+/// The text of a variable, label or function operand. Any other token is an error, in which case a
+/// placeholder name is returned so assembling can continue.
+fn operand(token: Option<&Token>) -> &str {
+    match token {
+        Some(Token::Operand(token)) => token,
+        Some(token) => {
+            err(format!("Expected a name, found {}", token));
+            "_"
+        }
+        None => "_",
+    }
 }
 
 static mut NAMED: bool = false;
 
 macro_rules! named_var {
     ($names:ident, $buffer:ident, $token:ident, $next:ident, $func:ident, $globals:ident) => {
-        if unsafe { NAMED } {
-            let index = $globals.binary_search(&$token.to_string());
-            if let Ok(id) = index {
-                $buffer.push_u32(id as u32)
-            }
-            else {
-                let t = format!("{}_{}", $func, $token);
-                if let hashbrown::hash_map::Entry::Vacant(e) = $names.entry(t.clone()) {
-                    e.insert(*$next);
-                    $buffer.push_u32(*$next);
-                    *$next += 1;
-                } else {
-                    $buffer.push_u32(*$names.get(&t).unwrap());
-                }
-            }
+        $buffer.push_u32(slot($token, $names, $next, $func, $globals))
+    };
+}
+
+/// Resolves a variable to its slot. The global variables take the first slots, followed by the numbered
+/// slots of index-addressed code, so `$0` is the first slot after the globals. Named variables are only
+/// allowed with `.named` and are given the slots after the highest numbered slot of the program.
+fn slot(token: &str, names: &mut HashMap<String, u32>, next_var: &mut u32, func: &str, globals: &[String]) -> u32 {
+    if token.starts_with(|c: char| c.is_ascii_digit()) {
+        return globals.len() as u32 + parse::<u32>(token, "variable index");
+    }
+    if let Ok(id) = globals.binary_search(&token.to_string()) {
+        return id as u32;
+    }
+    if !unsafe { NAMED } {
+        err(format!("Variable \"{}\" must be a slot number, use '.named' to name variables", token));
+        return 0;
+    }
+    if !is_ident(token) {
+        err(format!("Invalid variable name: \"{}\"", token));
+    }
+    *names.entry(format!("{}_{}", func, token)).or_insert_with(|| {
+        *next_var += 1;
+        *next_var - 1
+    })
+}
+
+/// The operands of a line that address variables, as the index of the token and the variable without
+/// its sigils. These are the destinations of instructions that write a variable, and operands starting
+/// with `$`, `&` or `*`, also after the `%` of an argument.
+pub fn variables(line: &Line) -> Vec<(usize, &str)> {
+    let Some(start) = line.tokens.iter().position(|t| matches!(t, Token::Instruction(_))) else {
+        return Vec::new();
+    };
+    let Token::Instruction(instruction) = &line.tokens[start] else {
+        unreachable!();
+    };
+    let mut variables = Vec::new();
+    for (i, token) in line.tokens.iter().enumerate().skip(start + 1) {
+        let Token::Operand(token) = token else {
+            continue;
+        };
+        if i == start + 1 && destination(instruction) {
+            let token = token.strip_prefix(VARIABLE).unwrap_or(token);
+            variables.push((i, token.strip_prefix(DEREF).unwrap_or(token)));
+            continue;
         }
-        else {
-            $buffer.push_u32($token.parse::<u32>().unwrap());
+        let token = token.strip_prefix(ARGUMENT).unwrap_or(token);
+        if token.starts_with([VARIABLE, REFERENCE, DEREF]) {
+            variables.push((i, &token[1..]));
         }
-    };
+    }
+    variables
 }
 
-fn push_str_var(buffer: &mut ByteBuffer, token: &str, names: &mut HashMap<String, u32>, next_var: &mut u32, func: &str, globals: &[String]) -> u32 {
+fn push_str_var(buffer: &mut ByteBuffer, token: &Token, names: &mut HashMap<String, u32>, next_var: &mut u32, func: &str, globals: &[String]) -> u32 {
+    let token = match token {
+        Token::String(s) => {
+            buffer.push_u8(LITERAL as u8);
+            buffer.push_string(s);
+            return s.len() as u32 + 5;
+        }
+        Token::Operand(token) => token.as_str(),
+        token => {
+            err(format!("Invalid string identifier: {}", token));
+            return 0;
+        }
+    };
     if token == "null" {
         buffer.push_u8(NULL as u8);
         return 1;
@@ -47,11 +154,6 @@ fn push_str_var(buffer: &mut ByteBuffer, token: &str, names: &mut HashMap<String
     buffer.push_u8(ident as u8);
     let mut offset = 1;
     match ident {
-        LITERAL => {
-            let str = format_escaped(str);
-            buffer.push_string(&str);
-            offset += str.len() as u32 + 4;
-        }
         VARIABLE | REFERENCE | DEREF => {
             named_var!(names, buffer, str, next_var, func, globals);
             offset += 4;
@@ -65,7 +167,7 @@ fn push_str_var(buffer: &mut ByteBuffer, token: &str, names: &mut HashMap<String
             }
             else {
                 buffer.push_u8(0);
-                buffer.push_u16(str.parse::<u16>().unwrap());
+                buffer.push_u16(parse::<u16>(str, "argument index"));
                 offset += 3;
             }
         }
@@ -74,15 +176,26 @@ fn push_str_var(buffer: &mut ByteBuffer, token: &str, names: &mut HashMap<String
     offset
 }
 
-fn push_val(buffer: &mut ByteBuffer, token: &str, names: &mut HashMap<String, u32>, next_var: &mut u32, func: &str, globals: &[String]) -> u32 {
-    let ident = token.chars().next().unwrap();
-    match ident {
-        LITERAL => {
+fn push_val(buffer: &mut ByteBuffer, token: &Token, names: &mut HashMap<String, u32>, next_var: &mut u32, func: &str, globals: &[String]) -> u32 {
+    let token = match token {
+        Token::String(s) => {
             buffer.push_u8(LITERAL as u8);
-            let str = format_escaped(token.split_at(1).1);
-            buffer.push_string(&str);
-            str.len() as u32 + 5
+            buffer.push_string(s);
+            return s.len() as u32 + 5;
+        }
+        Token::Char(c) => {
+            buffer.push_u8(CHAR as u8);
+            buffer.push_u32(*c as u32);
+            return 5;
+        }
+        Token::Operand(token) => token.as_str(),
+        token => {
+            err(format!("Expected a value, found {}", token));
+            return 0;
         }
+    };
+    let ident = token.chars().next().unwrap();
+    match ident {
         VARIABLE | REFERENCE | DEREF => {
             buffer.push_u8(ident as u8);
             let token = token.split_at(1).1;
@@ -96,20 +209,14 @@ fn push_val(buffer: &mut ByteBuffer, token: &str, names: &mut HashMap<String, u3
                 buffer.push_u8(VARIABLE as u8);
                 let str = str.split_at(1).1;
                 named_var!(names, buffer, str, next_var, func, globals);
-                5
+                6
             }
             else {
                 buffer.push_u8(0);
-                buffer.push_u16(str.parse::<u16>().unwrap());
-                3
+                buffer.push_u16(parse::<u16>(str, "argument index"));
+                4
             }
         }
-        '\'' => {
-            let c = parse_char(&token.replace('\'', ""), err);
-            buffer.push_u8(CHAR as u8);
-            buffer.push_u32(c as u32);
-            5
-        }
         _ => {
             if token == "true" {
                 buffer.push_u8(BOOLEAN_TRUE as u8);
@@ -125,30 +232,42 @@ fn push_val(buffer: &mut ByteBuffer, token: &str, names: &mut HashMap<String, u3
             }
             else if token.ends_with(CHAR) {
                 buffer.push_u8(CHAR as u8);
-                buffer.push_u32(token.split_at(token.len() - 1).0.parse::<u32>().unwrap());
+                buffer.push_u32(parse::<u32>(token.split_at(token.len() - 1).0, "character"));
                 5
             }
             else if token.contains('.') {
                 buffer.push_u8(FLOAT as u8);
-                buffer.push_f64(token.parse::<f64>().unwrap());
+                buffer.push_f64(parse::<f64>(token, "float"));
                 9
             }
             else {
                 buffer.push_u8(INTEGER as u8);
-                buffer.push_i64(token.parse::<i64>().unwrap());
+                buffer.push_i64(parse::<i64>(token, "value"));
                 9
             }
         }
     }
 }
 
-fn push_prim_val(buffer: &mut ByteBuffer, token: &str, names: &mut HashMap<String, u32>, next_var: &mut u32, func: &str, globals: &[String]) -> u32 {
-    let ident = token.chars().next().unwrap();
-    match ident {
-        LITERAL => {
+fn push_prim_val(buffer: &mut ByteBuffer, token: &Token, names: &mut HashMap<String, u32>, next_var: &mut u32, func: &str, globals: &[String]) -> u32 {
+    let token = match token {
+        Token::Char(c) => {
+            buffer.push_u8(CHAR as u8);
+            buffer.push_u32(*c as u32);
+            return 5;
+        }
+        Token::Operand(token) => token.as_str(),
+        Token::String(_) => {
             err("Argument cannot be of type string!".to_string());
-            0
+            return 0;
+        }
+        token => {
+            err(format!("Expected a value, found {}", token));
+            return 0;
         }
+    };
+    let ident = token.chars().next().unwrap();
+    match ident {
         VARIABLE | REFERENCE | DEREF => {
             buffer.push_u8(ident as u8);
             let token = token.split_at(1).1;
@@ -162,20 +281,14 @@ fn push_prim_val(buffer: &mut ByteBuffer, token: &str, names: &mut HashMap<Strin
                 buffer.push_u8(VARIABLE as u8);
                 let str = str.split_at(1).1;
                 named_var!(names, buffer, str, next_var, func, globals);
-                5
+                6
             }
             else {
                 buffer.push_u8(0);
-                buffer.push_u16(str.parse::<u16>().unwrap());
-                3
+                buffer.push_u16(parse::<u16>(str, "argument index"));
+                4
             }
         }
-        '\'' => {
-            let c = parse_char(&token.replace('\'', ""), err);
-            buffer.push_u8(CHAR as u8);
-            buffer.push_u32(c as u32);
-            5
-        }
         _ => {
             if token == "true" {
                 buffer.push_u8(BOOLEAN_TRUE as u8);
@@ -191,30 +304,42 @@ fn push_prim_val(buffer: &mut ByteBuffer, token: &str, names: &mut HashMap<Strin
             }
             else if token.ends_with(CHAR) {
                 buffer.push_u8(CHAR as u8);
-                buffer.push_u32(token.split_at(token.len() - 1).0.parse::<u32>().unwrap());
+                buffer.push_u32(parse::<u32>(token.split_at(token.len() - 1).0, "character"));
                 5
             }
             else  if token.contains('.') {
                 buffer.push_u8(FLOAT as u8);
-                buffer.push_f64(token.parse::<f64>().unwrap());
+                buffer.push_f64(parse::<f64>(token, "float"));
                 9
             }
             else {
                 buffer.push_u8(INTEGER as u8);
-                buffer.push_i64(token.parse::<i64>().unwrap());
+                buffer.push_i64(parse::<i64>(token, "value"));
                 9
             }
         }
     }
 }
 
-fn push_num_val(buffer: &mut ByteBuffer, token: &str, names: &mut HashMap<String, u32>, next_var: &mut u32, func: &str, globals: &[String]) -> u32 {
-    let ident = token.chars().next().unwrap();
-    match ident {
-        LITERAL => {
+fn push_num_val(buffer: &mut ByteBuffer, token: &Token, names: &mut HashMap<String, u32>, next_var: &mut u32, func: &str, globals: &[String]) -> u32 {
+    let token = match token {
+        Token::Char(c) => {
+            buffer.push_u8(CHAR as u8);
+            buffer.push_u32(*c as u32);
+            return 5;
+        }
+        Token::Operand(token) => token.as_str(),
+        Token::String(_) => {
             err("Argument cannot be of type string!".to_string());
-            0
+            return 0;
         }
+        token => {
+            err(format!("Expected a value, found {}", token));
+            return 0;
+        }
+    };
+    let ident = token.chars().next().unwrap();
+    match ident {
         VARIABLE | REFERENCE | DEREF => {
             buffer.push_u8(ident as u8);
             let token = token.split_at(1).1;
@@ -228,20 +353,14 @@ fn push_num_val(buffer: &mut ByteBuffer, token: &str, names: &mut HashMap<String
                 buffer.push_u8(VARIABLE as u8);
                 let str = str.split_at(1).1;
                 named_var!(names, buffer, str, next_var, func, globals);
-                5
+                6
             }
             else {
                 buffer.push_u8(0);
-                buffer.push_u16(str.parse::<u16>().unwrap());
-                3
+                buffer.push_u16(parse::<u16>(str, "argument index"));
+                4
             }
         }
-        '\'' => {
-            let c = parse_char(&token.replace('\'', ""), err);
-            buffer.push_u8(CHAR as u8);
-            buffer.push_u32(c as u32);
-            5
-        }
         _ => {
             if token == "true" || token == "false" {
                 err("Argument cannot be of type boolean!".to_string());
@@ -253,17 +372,17 @@ fn push_num_val(buffer: &mut ByteBuffer, token: &str, names: &mut HashMap<String
             }
             else if token.ends_with(CHAR) {
                 buffer.push_u8(CHAR as u8);
-                buffer.push_u32(token.split_at(token.len() - 1).0.parse::<u32>().unwrap());
+                buffer.push_u32(parse::<u32>(token.split_at(token.len() - 1).0, "character"));
                 5
             }
             else if token.contains('.') {
                 buffer.push_u8(FLOAT as u8);
-                buffer.push_f64(token.parse::<f64>().unwrap());
+                buffer.push_f64(parse::<f64>(token, "float"));
                 9
             }
             else {
                 buffer.push_u8(INTEGER as u8);
-                buffer.push_i64(token.parse::<i64>().unwrap());
+                buffer.push_i64(parse::<i64>(token, "value"));
                 9
             }
         }
@@ -274,7 +393,7 @@ macro_rules! named {
     ($names:ident, $buffer:ident, $tokens:ident, $next:ident, $func:ident, $globals:ident) => {
         {
             let mut offset = false;
-            let mut token = $tokens.next().unwrap();
+            let mut token = operand($tokens.next());
             if token.starts_with(VARIABLE) {
                 token = token.split_at(1).1;
             }
@@ -283,25 +402,7 @@ macro_rules! named {
                 $buffer.push_u8(DEREF as u8);
                 token = token.split_at(1).1;
             }
-            if unsafe { NAMED } {
-                let index = $globals.binary_search(&token.to_string());
-                if let Ok(id) = index {
-                    $buffer.push_u32(id as u32)
-                }
-                else {
-                    let ident = format!("{}_{}", $func, token);
-                    if let hashbrown::hash_map::Entry::Vacant(e) = $names.entry(ident.clone()) {
-                        e.insert($next);
-                        $buffer.push_u32($next);
-                        $next += 1;
-                    } else {
-                        $buffer.push_u32(*$names.get(&ident).unwrap());
-                    }
-                }
-            }
-            else {
-                $buffer.push_u32(token.parse::<u32>().unwrap());
-            }
+            $buffer.push_u32(slot(token, &mut $names, &mut $next, &$func, &$globals));
             if offset {
                 5
             }
@@ -312,19 +413,41 @@ macro_rules! named {
     };
 }
 
+/// The number of operands of an instruction, or `None` if there is no such instruction.
+fn operands(instruction: &str) -> Option<usize> {
+    match instruction.to_ascii_uppercase().as_str() {
+        "NOP" | "END" | "RET" => Some(0),
+        "JMP" | "JE" | "JNE" | "JG" | "JGE" | "JL" | "JLE" | "CALL" | "INC" | "DEC" | "NOT" | "NEG" | "PUSH" | "POP"
+        | "PRINT" | "SH" | "PUSH_RET" | "POP_RET" | "PRINT_INLINE" | "EPRINT" | "READ_LINE" | "READ_ALL" | "FORMAT" => Some(1),
+        "MOV" | "CPY" | "CMP" | "JZ" | "JNZ" | "JN" | "JNN" | "ADD" | "SUB" | "MUL" | "DIV" | "MOD" | "AND" | "OR"
+        | "XOR" | "SHL" | "SHR" | "SAR" => Some(2),
+        _ => None,
+    }
+}
+
+/// Whether the first operand of an instruction is a variable that it writes to.
+pub fn destination(instruction: &str) -> bool {
+    matches!(instruction.to_ascii_uppercase().as_str(), "MOV" | "CPY" | "INC" | "DEC" | "ADD" | "SUB" | "MUL" | "DIV" | "MOD" | "AND" | "OR"
+        | "XOR" | "NOT" | "NEG" | "SHL" | "SHR" | "SAR" | "POP" | "POP_RET" | "READ_LINE" | "READ_ALL" | "FORMAT")
+}
+
 pub fn jump(token: &str, index: u32, labels: &mut HashMap<String, u32>, calls: &mut Vec<u32>) -> u32 {
     if token.starts_with('-') {
-        let location = index - token.split_at(1).1.parse::<u32>().unwrap();
+        let offset = parse::<u32>(token.split_at(1).1, "relative jump");
+        let location = index.checked_sub(offset).unwrap_or_else(|| {
+            err(format!("Relative jump {} goes before the first instruction!", token));
+            0
+        });
         calls.push(location);
         calls.len() as u32 - 1
     }
     else if token.starts_with('+') {
-        let location = index + token.split_at(1).1.parse::<u32>().unwrap();
+        let location = index + parse::<u32>(token.split_at(1).1, "relative jump");
         calls.push(location);
         calls.len() as u32 - 1
     }
-    else if token.chars().next().unwrap().is_ascii_digit() {
-        let location = token.parse::<u32>().unwrap();
+    else if token.starts_with(|c: char| c.is_ascii_digit()) {
+        let location = parse::<u32>(token, "jump address");
         calls.push(location);
         calls.len() as u32 - 1
     }
@@ -341,8 +464,9 @@ pub fn jump(token: &str, index: u32, labels: &mut HashMap<String, u32>, calls: &
 
 pub fn assemble(input: String) -> Vec<u8> {
     let (globals, usages, _, _) = extract(&input);
+    let lines = lex(&input).unwrap_or_default();
     let mut buffer = ByteBuffer::new();
-    let mut tokens = input.split_whitespace();
+    let mut tokens: std::slice::Iter<Token>;
     let mut index = 12;
     let mut labels = HashMap::new();
     let mut jump_calls = Vec::new();
@@ -350,18 +474,19 @@ pub fn assemble(input: String) -> Vec<u8> {
     let mut jumps = Vec::new();
     let mut calls = Vec::new();
     let mut names: HashMap<String, u32> = HashMap::new();
-    let mut next_var = globals.len() as u32;
+    let slots = lines.iter().flat_map(variables).filter_map(|(_, v)| v.parse::<u32>().ok().map(|v| v + 1)).max().unwrap_or(0);
+    let mut next_var = globals.len() as u32 + slots;
     let mut func = "".to_string();
     let mut idents: HashMap<String, u32> = HashMap::new();
     let mut functions: Vec<u32> = Vec::new();
     let mut next_fn = 0;
     let mut returned = true;
     let mut table = false;
+    let mut defined = HashSet::new();
+    let mut targets = Vec::new();
+    let mut called = Vec::new();
 
-    if input.starts_with(".named") {
-        unsafe { NAMED = true; }
-        tokens.next();
-    }
+    unsafe { NAMED = input.starts_with(".named"); }
 
     buffer.push_u32(0);
     buffer.push_u32(0);
@@ -399,33 +524,18 @@ pub fn assemble(input: String) -> Vec<u8> {
 
     macro_rules! jmp {
         () => {
-            let token = tokens.next().unwrap();
+            let token = operand(tokens.next());
             if token.starts_with(VARIABLE) {
                 table = true;
                 let token = token.split_at(1).1;
                 buffer.push_u8(VARIABLE as u8);
-                if unsafe { NAMED } {
-                    let index = globals.binary_search(&token.to_string());
-                    if let Ok(id) = index {
-                        buffer.push_u32(id as u32)
-                    }
-                    else {
-                        let ident = format!("{}_{}", func, token);
-                        if let hashbrown::hash_map::Entry::Vacant(e) = names.entry(ident.clone()) {
-                            e.insert(next_var);
-                            buffer.push_u32(next_var);
-                            next_var += 1;
-                        } else {
-                            buffer.push_u32(*names.get(&ident).unwrap());
-                        }
-                    }
-                }
-                else {
-                    buffer.push_u32(token.parse::<u32>().unwrap());
-                }
+                buffer.push_u32(slot(token, &mut names, &mut next_var, &func, &globals));
                 index += 5;
             }
             else {
+                if !token.starts_with(|c: char| c.is_ascii_digit() || c == '+' || c == '-') {
+                    targets.push((token.to_string(), location()));
+                }
                 jumps.push(buffer.get_wpos());
                 buffer.push_u32(jump(token, addresses.len() as u32 - 1, &mut labels, &mut jump_calls));
                 index += 4;
@@ -433,259 +543,305 @@ pub fn assemble(input: String) -> Vec<u8> {
         };
     }
 
-    while let Some(s) = tokens.next() {
-        if s == ".global" || s == ".use" || s == ".extern" {
-            tokens.next();
-            continue;
-        }
-        else if s == ".named" {
-            continue;
-        }
-        else if s.starts_with('@') {
-            if !returned {
-                err("Labels and function names cannot start with a digit!".to_string());
-            }
-            let mut ident = s.split_at(1).1;
-            let first = ident.chars().next().unwrap();
-            if !(first.is_ascii_alphabetic() || first == '_') {
-                err("Labels and function names must start with an ascii alphabetic character or underscore!".to_string());
-            }
-            if ident.ends_with(':') {
-                ident = ident.split_at(ident.len() - 1).0;
-            }
-            if !idents.contains_key(ident) {
-                idents.insert(ident.to_string(), next_fn);
-                next_fn += 1;
-            }
-            let id = idents[ident];
-            if functions.len() <= id as usize {
-                functions.resize(id as usize + 1, 0);
-            }
-            func = ident.to_string();
-            functions[id as usize] = index;
-            returned = false;
+    let mut locator = Locator::new("");
+    for line in &lines {
+        let Some(at) = locator.locate(line) else {
             continue;
-        }
-        else if s.starts_with('.') {
-            let mut ident = s.split_at(1).1;
-            let first = ident.chars().next().unwrap();
-            if !(first.is_ascii_alphabetic() || first == '_') {
-                err("Labels and function names must start with an ascii alphabetic character or underscore!".to_string());
-            }
-            if ident.ends_with(':') {
-                ident = ident.split_at(ident.len() - 1).0;
-            }
-            if !labels.contains_key(ident) {
-                jump_calls.push(addresses.len() as u32);
-                labels.insert(ident.to_string(), jump_calls.len() as u32 - 1);
-            }
-            else {
-                jump_calls[labels[ident] as usize] = addresses.len() as u32;
-            }
-            continue;
-        }
-        if func.is_empty() {
-           err("Symbols outside functions are not allowed! If you want to execute instructions, put them into the @main function!".to_string());
-        }
-        addresses.push(index);
-        index += 1;
-        match s.to_ascii_uppercase().as_str() {
-            "NOP" => buffer.push_u8(NOOP),
-            "END" => {
-                buffer.push_u8(END);
-                returned = true;
-            }
-            "MOV" => {
-                buffer.push_u8(MOV);
-                get_named!();
-                push_val!();
-            }
-            "JMP" => {
-                buffer.push_u8(JMP);
-                jmp!();
-            }
-            "JZ" => {
-                buffer.push_u8(JZ);
-                push_val!();
-                jmp!();
-            }
-            "JNZ" => {
-                buffer.push_u8(JNZ);
-                push_val!();
-                jmp!();
-            }
-            "JN" => {
-                buffer.push_u8(JN);
-                push_val!();
-                jmp!();
-            }
-            "JNN" => {
-                buffer.push_u8(JNN);
-                push_val!();
-                jmp!();
-            }
-            "CMP" => {
-                buffer.push_u8(CMP);
-                push_val!();
-                push_val!();
-            }
-            "JE" => {
-                buffer.push_u8(JE);
-                jmp!();
-            }
-            "JNE" => {
-                buffer.push_u8(JNE);
-                jmp!();
-            }
-            "JG" => {
-                buffer.push_u8(JG);
-                jmp!();
-            }
-            "JGE" => {
-                buffer.push_u8(JGE);
-                jmp!();
-            }
-            "JL" => {
-                buffer.push_u8(JL);
-                jmp!();
-            }
-            "JLE" => {
-                buffer.push_u8(JLE);
-                jmp!();
-            }
-            "CALL" => {
-                buffer.push_u8(CALL);
-                let call = tokens.next().unwrap();
-                let first = call.chars().next().unwrap();
-                if !(first.is_ascii_alphabetic() || first == '_') {
-                    err("Labels and function names must start with an ascii alphabetic character or underscore!".to_string());
-                }
-                if usages.binary_search(&call.to_ascii_uppercase()).is_ok() {
-                    buffer.push_u8(BUILTIN as u8);
-                    buffer.push_u32(*BUILTIN_FUNCTIONS.get(call.to_ascii_uppercase().as_str()).unwrap());
-                    index += 5;
+        };
+        LOCATION.set(at);
+        tokens = line.tokens.iter();
+        while let Some(token) = tokens.next() {
+            let s = match token {
+                Token::Directive(_) => break,
+                Token::Function(ident) => {
+                    if !is_ident(ident) {
+                        err("Labels and function names must start with an ascii alphabetic character or underscore!".to_string());
+                    }
+                    if !returned {
+                        err(format!("Function \"{}\" must end with ret or end before function \"{}\" starts!", func, ident));
+                    }
+                    if !defined.insert(format!("@{}", ident)) {
+                        err(format!("Duplicate function \"{}\"!", ident));
+                    }
+                    if !idents.contains_key(ident) {
+                        idents.insert(ident.to_string(), next_fn);
+                        next_fn += 1;
+                    }
+                    let id = idents[ident];
+                    if functions.len() <= id as usize {
+                        functions.resize(id as usize + 1, 0);
+                    }
+                    func = ident.to_string();
+                    functions[id as usize] = index;
+                    returned = false;
                     continue;
                 }
-                if !idents.contains_key(call) {
-                    idents.insert(call.to_string(), next_fn);
-                    next_fn += 1;
+                Token::Label(ident) => {
+                    if !is_ident(ident) {
+                        err("Labels and function names must start with an ascii alphabetic character or underscore!".to_string());
+                    }
+                    if !defined.insert(ident.to_string()) {
+                        err(format!("Duplicate label \"{}\"!", ident));
+                    }
+                    if !labels.contains_key(ident) {
+                        jump_calls.push(addresses.len() as u32);
+                        labels.insert(ident.to_string(), jump_calls.len() as u32 - 1);
+                    }
+                    else {
+                        jump_calls[labels[ident] as usize] = addresses.len() as u32;
+                    }
+                    continue;
                 }
-                let id = idents[call];
-                calls.push(buffer.get_wpos());
-                buffer.push_u32(id);
-                index += 4;
-            }
-            "RET" => {
-                buffer.push_u8(RET);
-                returned = true;
-            }
-            "INC" => {
-                buffer.push_u8(INC);
-                get_named!();
-            }
-            "DEC" => {
-                buffer.push_u8(DEC);
-                get_named!();
-            }
-            "ADD" => {
-                buffer.push_u8(ADD);
-                get_named!();
-                push_num!();
-            }
-            "SUB" => {
-                buffer.push_u8(SUB);
-                get_named!();
-                push_num!();
-            }
-            "MUL" => {
-                buffer.push_u8(MUL);
-                get_named!();
-                push_num!();
-            }
-            "DIV" => {
-                buffer.push_u8(DIV);
-                get_named!();
-                push_num!();
-            }
-            "MOD" => {
-                buffer.push_u8(MOD);
-                get_named!();
-                push_num!();
-            }
-            "AND" => {
-                buffer.push_u8(AND);
-                get_named!();
-                push_prim!();
-            }
-            "OR" => {
-                buffer.push_u8(OR);
-                get_named!();
-                push_prim!();
-            }
-            "NOT" => {
-                buffer.push_u8(NOT);
-                get_named!();
-            }
-            "NEG" => {
-                buffer.push_u8(NEG);
-                get_named!();
-            }
-            "XOR" => {
-                buffer.push_u8(XOR);
-                get_named!();
-                push_prim!();
-            }
-            "SHL" => {
-                buffer.push_u8(SHL);
-                get_named!();
-                push_num!();
-            }
-            "SHR" => {
-                buffer.push_u8(SHR);
-                get_named!();
-                push_num!();
-            }
-            "SAR" => {
-                buffer.push_u8(SAR);
-                get_named!();
-                push_num!();
-            }
-            "PUSH" => {
-                buffer.push_u8(PUSH);
-                push_val!();
-            }
-            "POP" => {
-                buffer.push_u8(POP);
-                get_named!();
-            }
-            "PRINT" => {
-                buffer.push_u8(PRINT);
-                push_str!();
-            }
-            "SH" => {
-                buffer.push_u8(SH);
-                push_str!();
-            }
-            "PUSH_RET" => {
-                buffer.push_u8(PUSH_RET);
-                push_val!();
-            }
-            "POP_RET" => {
-                buffer.push_u8(POP_RET);
-                get_named!();
-            }
-            "CPY" => {
-                buffer.push_u8(CPY);
-                get_named!();
-                push_val!();
+                Token::Instruction(s) => s.as_str(),
+                t => {
+                    err(format!("Expected an instruction, found {}", t));
+                    break;
+                }
+            };
+            if func.is_empty() {
+               err("Symbols outside functions are not allowed! If you want to execute instructions, put them into the @main function!".to_string());
+            }
+            let Some(expected) = operands(s) else {
+                err(format!("Unknown instruction: {}", s));
+                break;
+            };
+            let given = tokens.len();
+            if given != expected {
+                err(format!("{} expects {} operand{}, got {}: {}", s.to_ascii_lowercase(), expected, if expected == 1 { "" } else { "s" }, given, line));
+                break;
+            }
+            addresses.push(index);
+            index += 1;
+            match s.to_ascii_uppercase().as_str() {
+                "NOP" => buffer.push_u8(NOOP),
+                "END" => {
+                    buffer.push_u8(END);
+                    returned = true;
+                }
+                "MOV" => {
+                    buffer.push_u8(MOV);
+                    get_named!();
+                    push_val!();
+                }
+                "JMP" => {
+                    buffer.push_u8(JMP);
+                    jmp!();
+                }
+                "JZ" => {
+                    buffer.push_u8(JZ);
+                    push_val!();
+                    jmp!();
+                }
+                "JNZ" => {
+                    buffer.push_u8(JNZ);
+                    push_val!();
+                    jmp!();
+                }
+                "JN" => {
+                    buffer.push_u8(JN);
+                    push_val!();
+                    jmp!();
+                }
+                "JNN" => {
+                    buffer.push_u8(JNN);
+                    push_val!();
+                    jmp!();
+                }
+                "CMP" => {
+                    buffer.push_u8(CMP);
+                    push_val!();
+                    push_val!();
+                }
+                "JE" => {
+                    buffer.push_u8(JE);
+                    jmp!();
+                }
+                "JNE" => {
+                    buffer.push_u8(JNE);
+                    jmp!();
+                }
+                "JG" => {
+                    buffer.push_u8(JG);
+                    jmp!();
+                }
+                "JGE" => {
+                    buffer.push_u8(JGE);
+                    jmp!();
+                }
+                "JL" => {
+                    buffer.push_u8(JL);
+                    jmp!();
+                }
+                "JLE" => {
+                    buffer.push_u8(JLE);
+                    jmp!();
+                }
+                "CALL" => {
+                    buffer.push_u8(CALL);
+                    let call = operand(tokens.next());
+                    if !is_ident(call) {
+                        err("Labels and function names must start with an ascii alphabetic character or underscore!".to_string());
+                    }
+                    if usages.binary_search(&call.to_ascii_uppercase()).is_ok() {
+                        buffer.push_u8(BUILTIN as u8);
+                        buffer.push_u32(*BUILTIN_FUNCTIONS.get(call.to_ascii_uppercase().as_str()).unwrap());
+                        index += 5;
+                        continue;
+                    }
+                    called.push((call.to_string(), location()));
+                    if !idents.contains_key(call) {
+                        idents.insert(call.to_string(), next_fn);
+                        next_fn += 1;
+                    }
+                    let id = idents[call];
+                    calls.push(buffer.get_wpos());
+                    buffer.push_u32(id);
+                    index += 4;
+                }
+                "RET" => {
+                    buffer.push_u8(RET);
+                    returned = true;
+                }
+                "INC" => {
+                    buffer.push_u8(INC);
+                    get_named!();
+                }
+                "DEC" => {
+                    buffer.push_u8(DEC);
+                    get_named!();
+                }
+                "ADD" => {
+                    buffer.push_u8(ADD);
+                    get_named!();
+                    push_num!();
+                }
+                "SUB" => {
+                    buffer.push_u8(SUB);
+                    get_named!();
+                    push_num!();
+                }
+                "MUL" => {
+                    buffer.push_u8(MUL);
+                    get_named!();
+                    push_num!();
+                }
+                "DIV" => {
+                    buffer.push_u8(DIV);
+                    get_named!();
+                    push_num!();
+                }
+                "MOD" => {
+                    buffer.push_u8(MOD);
+                    get_named!();
+                    push_num!();
+                }
+                "AND" => {
+                    buffer.push_u8(AND);
+                    get_named!();
+                    push_prim!();
+                }
+                "OR" => {
+                    buffer.push_u8(OR);
+                    get_named!();
+                    push_prim!();
+                }
+                "NOT" => {
+                    buffer.push_u8(NOT);
+                    get_named!();
+                }
+                "NEG" => {
+                    buffer.push_u8(NEG);
+                    get_named!();
+                }
+                "XOR" => {
+                    buffer.push_u8(XOR);
+                    get_named!();
+                    push_prim!();
+                }
+                "SHL" => {
+                    buffer.push_u8(SHL);
+                    get_named!();
+                    push_num!();
+                }
+                "SHR" => {
+                    buffer.push_u8(SHR);
+                    get_named!();
+                    push_num!();
+                }
+                "SAR" => {
+                    buffer.push_u8(SAR);
+                    get_named!();
+                    push_num!();
+                }
+                "PUSH" => {
+                    buffer.push_u8(PUSH);
+                    push_val!();
+                }
+                "POP" => {
+                    buffer.push_u8(POP);
+                    get_named!();
+                }
+                "PRINT" => {
+                    buffer.push_u8(PRINT);
+                    push_str!();
+                }
+                "SH" => {
+                    buffer.push_u8(SH);
+                    push_str!();
+                }
+                "PUSH_RET" => {
+                    buffer.push_u8(PUSH_RET);
+                    push_val!();
+                }
+                "POP_RET" => {
+                    buffer.push_u8(POP_RET);
+                    get_named!();
+                }
+                "PRINT_INLINE" => {
+                    buffer.push_u8(PRINT_INLINE);
+                    push_str!();
+                }
+                "EPRINT" => {
+                    buffer.push_u8(EPRINT);
+                    push_str!();
+                }
+                "READ_LINE" => {
+                    buffer.push_u8(READ_LINE);
+                    get_named!();
+                }
+                "READ_ALL" => {
+                    buffer.push_u8(READ_ALL);
+                    get_named!();
+                }
+                "FORMAT" => {
+                    buffer.push_u8(FORMAT);
+                    get_named!();
+                }
+                "CPY" => {
+                    buffer.push_u8(CPY);
+                    get_named!();
+                    push_val!();
+                }
+                _ => err(format!("Unknown instruction: {}", s)),
             }
-            _ => err(format!("Unknown instruction: {}", s)),
         }
     }
 
+    for (label, at) in targets {
+        if defined.insert(label.clone()) {
+            err_at(&at, format!("Jump to unknown label \"{}\"", label));
+        }
+    }
+    for (function, at) in called {
+        if defined.insert(format!("@{}", function)) {
+            err_at(&at, format!("Call to unknown function \"{}\"", function));
+        }
+    }
+    set_location("", 0);
     if !idents.contains_key("main") {
         err("No main function found".to_string());
     }
+    report();
 
     buffer.set_wpos(0);
     buffer.push_u32(functions[idents["main"] as usize]);
@@ -700,22 +856,25 @@ pub fn assemble(input: String) -> Vec<u8> {
     for jump in jumps {
         buffer.set_rpos(jump);
         let addr = buffer.pop_u32().unwrap() as usize;
-        if addr >= jump_calls.len() {
-            err(format!("Invalid jump address: {}", addr));
-        }
+        let Some(&address) = jump_calls.get(addr).and_then(|a| addresses.get(*a as usize)) else {
+            err(format!("Jump to instruction {} is out of range, there are only {} instructions", jump_calls.get(addr).copied().unwrap_or(addr as u32), addresses.len()));
+            continue;
+        };
         buffer.set_wpos(jump);
-        buffer.write_u32(addresses[jump_calls[addr] as usize]);
+        buffer.write_u32(address);
     }
 
     for call in calls {
         buffer.set_rpos(call);
         let func = buffer.pop_u32().unwrap() as usize;
-        if func >= functions.len() {
+        let Some(&address) = functions.get(func) else {
             err(format!("Invalid call address: {}", func));
-        }
+            continue;
+        };
         buffer.set_wpos(call);
-        buffer.write_u32(functions[func]);
+        buffer.write_u32(address);
     }
+    report();
 
     buffer.into_vec()
 }
@@ -726,46 +885,68 @@ pub fn extract(s: &str) -> (Vec<String>, Vec<String>, Vec<String>, Vec<String>)
     let mut externs = Vec::new();
     let mut labels = Vec::new();
 
-    let mut tokens = s.split_whitespace();
-
-    while let Some(token) = tokens.next() {
-        if !token.starts_with('.') || token == ".named" {
+    set_location("", 0);
+    let lines = lex(s).unwrap_or_else(|e| {
+        err(e);
+        Vec::new()
+    });
+    let mut locator = Locator::new("");
+    for line in &lines {
+        let Some(at) = locator.locate(line) else {
             continue;
-        }
-        else if token == ".global" {
-            let name = tokens.next().expect(".global must be followed by a name!").to_string();
-            if globals.contains(&name) {
-                err(format!("Duplicate global variables named \"{}\"", name));
+        };
+        LOCATION.set(at);
+        match &line.tokens[..] {
+            [Token::Directive(d), rest @ ..] if d == "global" => {
+                let Some(Token::Operand(name)) = rest.first() else {
+                    err(".global must be followed by a name!".to_string());
+                    continue;
+                };
+                if globals.contains(name) {
+                    err(format!("Duplicate global variables named \"{}\"", name));
+                }
+                globals.push(name.clone());
             }
-            globals.push(name);
-        }
-        else if token == ".use" {
-            let usage = tokens.next().expect(".use must be followed by a builtin function name!").to_ascii_uppercase();
-            if !BUILTIN_FUNCTIONS.contains_key(&usage) {
-                err(format!("Unknown builtin function: {}", usage));
+            [Token::Directive(d), rest @ ..] if d == "use" => {
+                let Some(Token::Operand(usage)) = rest.first() else {
+                    err(".use must be followed by a builtin function name!".to_string());
+                    continue;
+                };
+                let usage = usage.to_ascii_uppercase();
+                if !BUILTIN_FUNCTIONS.contains_key(&usage) {
+                    err(format!("Unknown builtin function: {}", usage));
+                }
+                usages.push(usage);
             }
-            usages.push(usage);
-        }
-        else if token == ".extern" {
-            let name = tokens.next().expect(".extern must be followed by a module name!").to_string();
-            externs.push(name);
-        }
-        else {
-            let mut token = token.split_at(1).1;
-            let first = token.chars().next().unwrap();
-            if !(first.is_ascii_alphabetic() || first == '_') {
-                err("Labels and function names must start with an ascii alphabetic character or underscore!".to_string());
+            [Token::Directive(d), rest @ ..] if d == "extern" => {
+                let Some(Token::Operand(name)) = rest.first() else {
+                    err(".extern must be followed by a module name!".to_string());
+                    continue;
+                };
+                externs.push(name.clone());
             }
-            if token.ends_with(':') {
-                token = token.split_at(token.len() - 1).0;
+            [Token::Directive(d), rest @ ..] if d == "export" => {
+                if !matches!(rest.first(), Some(Token::Operand(_))) {
+                    err(".export must be followed by a function name!".to_string());
+                }
+            }
+            tokens => {
+                for token in tokens {
+                    if let Token::Label(label) = token {
+                        if !is_ident(label) {
+                            err("Labels and function names must start with an ascii alphabetic character or underscore!".to_string());
+                        }
+                        labels.push(label.clone());
+                    }
+                }
             }
-            labels.push(token.to_string());
         }
     }
+    report();
 
     globals.sort_unstable();
     usages.sort_unstable();
     usages.dedup();
 
     (globals, usages, externs, labels)
-}
\ No newline at end of file
+}
@@ -39,6 +39,11 @@ pub const SH: u8 = 35;
 pub const PUSH_RET: u8 = 36;
 pub const POP_RET: u8 = 37;
 pub const CPY: u8 = 38;
+pub const PRINT_INLINE: u8 = 39;
+pub const EPRINT: u8 = 40;
+pub const READ_LINE: u8 = 41;
+pub const READ_ALL: u8 = 42;
+pub const FORMAT: u8 = 43;
 
 pub const BUILTIN: char = '@';
 pub const LITERAL: char = '#';
@@ -59,6 +64,101 @@ pub const GIT_COMMIT_DEFAULT: u32 = 130;
 pub const GIT_COMMIT: u32 = 131;
 pub const GIT_PUSH_UPSTREAM: u32 = 132;
 pub const GIT_PUSH: u32 = 133;
+pub const SH_IN: u32 = 134;
+pub const SHOUT: u32 = 135;
+pub const SHOUT_ERR: u32 = 136;
+pub const SHOUT_IN: u32 = 137;
+pub const PROC: u32 = 138;
+pub const PROC_ARG: u32 = 139;
+pub const PROC_ENV: u32 = 140;
+pub const PROC_CWD: u32 = 141;
+pub const PROC_CAPTURE: u32 = 142;
+pub const PROC_RUN: u32 = 143;
+pub const PROC_SPAWN: u32 = 144;
+pub const PROC_WAIT: u32 = 145;
+pub const PROC_OUTPUT: u32 = 146;
+pub const PROC_ERROR: u32 = 147;
+pub const PROC_STATUS: u32 = 148;
+pub const READ_FILE: u32 = 149;
+pub const WRITE_FILE: u32 = 150;
+pub const APPEND_FILE: u32 = 151;
+pub const EXISTS: u32 = 152;
+pub const IS_DIR: u32 = 153;
+pub const LIST_DIR: u32 = 154;
+pub const MKDIR: u32 = 155;
+pub const REMOVE: u32 = 156;
+pub const COPY: u32 = 157;
+pub const RENAME: u32 = 158;
+pub const FILE_SIZE: u32 = 159;
+pub const MTIME: u32 = 160;
+pub const LAST_ERROR: u32 = 161;
+pub const LIST_NEW: u32 = 162;
+pub const LIST_LEN: u32 = 163;
+pub const LIST_GET: u32 = 164;
+pub const LIST_PUSH: u32 = 165;
+pub const LEN: u32 = 166;
+pub const SUBSTRING: u32 = 167;
+pub const INDEX_OF: u32 = 168;
+pub const SPLIT: u32 = 169;
+pub const JOIN: u32 = 170;
+pub const TRIM: u32 = 171;
+pub const REPLACE: u32 = 172;
+pub const UPPER: u32 = 173;
+pub const LOWER: u32 = 174;
+pub const STARTS_WITH: u32 = 175;
+pub const ENDS_WITH: u32 = 176;
+pub const CHAR_AT: u32 = 177;
+pub const REPEAT: u32 = 178;
+pub const FORMAT_NUMBER: u32 = 179;
+pub const TO_INT: u32 = 180;
+pub const TO_FLOAT: u32 = 181;
+pub const TO_STRING: u32 = 182;
+pub const TO_CHAR: u32 = 183;
+pub const TO_BOOL: u32 = 184;
+pub const TYPEOF: u32 = 185;
+pub const ABS: u32 = 186;
+pub const MIN: u32 = 187;
+pub const MAX: u32 = 188;
+pub const POW: u32 = 189;
+pub const SQRT: u32 = 190;
+pub const FLOOR: u32 = 191;
+pub const CEIL: u32 = 192;
+pub const ROUND: u32 = 193;
+pub const SIN: u32 = 194;
+pub const COS: u32 = 195;
+pub const TAN: u32 = 196;
+pub const ASIN: u32 = 197;
+pub const ACOS: u32 = 198;
+pub const ATAN: u32 = 199;
+pub const ATAN2: u32 = 200;
+pub const LOG: u32 = 201;
+pub const LOG10: u32 = 202;
+pub const EXP: u32 = 203;
+pub const GCD: u32 = 204;
+pub const CLAMP: u32 = 205;
+pub const ENV: u32 = 206;
+pub const SET_ENV: u32 = 207;
+pub const UNSET_ENV: u32 = 208;
+pub const CWD: u32 = 209;
+pub const CHDIR: u32 = 210;
+pub const ARG_COUNT: u32 = 211;
+pub const PID: u32 = 212;
+pub const EXIT: u32 = 213;
+pub const GIT_BRANCH: u32 = 214;
+pub const GIT_STATUS: u32 = 215;
+pub const GIT_LOG: u32 = 216;
+pub const GIT_DIFF_STATS: u32 = 217;
+pub const GIT_TAG: u32 = 218;
+pub const GIT_CHECKOUT: u32 = 219;
+pub const GIT_FETCH: u32 = 220;
+pub const GIT_PULL: u32 = 221;
+pub const MAP_NEW: u32 = 222;
+pub const MAP_GET: u32 = 223;
+pub const MAP_SET: u32 = 224;
+pub const MAP_HAS: u32 = 225;
+pub const MAP_KEYS: u32 = 226;
+pub const JSON_PARSE: u32 = 227;
+pub const JSON_STRINGIFY: u32 = 228;
 
 pub static BUILTIN_FUNCTIONS: Map<&'static str, u32> = phf_map! {
     "GIT_ADD_ALL" => GIT_ADD_ALL,
@@ -66,5 +166,100 @@ pub static BUILTIN_FUNCTIONS: Map<&'static str, u32> = phf_map! {
     "GIT_COMMIT_DEFAULT" => GIT_COMMIT_DEFAULT,
     "GIT_COMMIT" => GIT_COMMIT,
     "GIT_PUSH_UPSTREAM" => GIT_PUSH_UPSTREAM,
-    "GIT_PUSH" => GIT_PUSH
+    "GIT_PUSH" => GIT_PUSH,
+    "SH_IN" => SH_IN,
+    "SHOUT" => SHOUT,
+    "SHOUT_ERR" => SHOUT_ERR,
+    "SHOUT_IN" => SHOUT_IN,
+    "PROC" => PROC,
+    "PROC_ARG" => PROC_ARG,
+    "PROC_ENV" => PROC_ENV,
+    "PROC_CWD" => PROC_CWD,
+    "PROC_CAPTURE" => PROC_CAPTURE,
+    "PROC_RUN" => PROC_RUN,
+    "PROC_SPAWN" => PROC_SPAWN,
+    "PROC_WAIT" => PROC_WAIT,
+    "PROC_OUTPUT" => PROC_OUTPUT,
+    "PROC_ERROR" => PROC_ERROR,
+    "PROC_STATUS" => PROC_STATUS,
+    "READ_FILE" => READ_FILE,
+    "WRITE_FILE" => WRITE_FILE,
+    "APPEND_FILE" => APPEND_FILE,
+    "EXISTS" => EXISTS,
+    "IS_DIR" => IS_DIR,
+    "LIST_DIR" => LIST_DIR,
+    "MKDIR" => MKDIR,
+    "REMOVE" => REMOVE,
+    "COPY" => COPY,
+    "RENAME" => RENAME,
+    "FILE_SIZE" => FILE_SIZE,
+    "MTIME" => MTIME,
+    "LAST_ERROR" => LAST_ERROR,
+    "LIST_NEW" => LIST_NEW,
+    "LIST_LEN" => LIST_LEN,
+    "LIST_GET" => LIST_GET,
+    "LIST_PUSH" => LIST_PUSH,
+    "LEN" => LEN,
+    "SUBSTRING" => SUBSTRING,
+    "INDEX_OF" => INDEX_OF,
+    "SPLIT" => SPLIT,
+    "JOIN" => JOIN,
+    "TRIM" => TRIM,
+    "REPLACE" => REPLACE,
+    "UPPER" => UPPER,
+    "LOWER" => LOWER,
+    "STARTS_WITH" => STARTS_WITH,
+    "ENDS_WITH" => ENDS_WITH,
+    "CHAR_AT" => CHAR_AT,
+    "REPEAT" => REPEAT,
+    "FORMAT_NUMBER" => FORMAT_NUMBER,
+    "INT" => TO_INT,
+    "FLOAT" => TO_FLOAT,
+    "STRING" => TO_STRING,
+    "CHAR" => TO_CHAR,
+    "BOOL" => TO_BOOL,
+    "TYPEOF" => TYPEOF,
+    "ABS" => ABS,
+    "MIN" => MIN,
+    "MAX" => MAX,
+    "POW" => POW,
+    "SQRT" => SQRT,
+    "FLOOR" => FLOOR,
+    "CEIL" => CEIL,
+    "ROUND" => ROUND,
+    "SIN" => SIN,
+    "COS" => COS,
+    "TAN" => TAN,
+    "ASIN" => ASIN,
+    "ACOS" => ACOS,
+    "ATAN" => ATAN,
+    "ATAN2" => ATAN2,
+    "LOG" => LOG,
+    "LOG10" => LOG10,
+    "EXP" => EXP,
+    "GCD" => GCD,
+    "CLAMP" => CLAMP,
+    "ENV" => ENV,
+    "SET_ENV" => SET_ENV,
+    "UNSET_ENV" => UNSET_ENV,
+    "CWD" => CWD,
+    "CHDIR" => CHDIR,
+    "ARG_COUNT" => ARG_COUNT,
+    "PID" => PID,
+    "EXIT" => EXIT,
+    "GIT_BRANCH" => GIT_BRANCH,
+    "GIT_STATUS" => GIT_STATUS,
+    "GIT_LOG" => GIT_LOG,
+    "GIT_DIFF_STATS" => GIT_DIFF_STATS,
+    "GIT_TAG" => GIT_TAG,
+    "GIT_CHECKOUT" => GIT_CHECKOUT,
+    "GIT_FETCH" => GIT_FETCH,
+    "GIT_PULL" => GIT_PULL,
+    "MAP_NEW" => MAP_NEW,
+    "MAP_GET" => MAP_GET,
+    "MAP_SET" => MAP_SET,
+    "MAP_HAS" => MAP_HAS,
+    "MAP_KEYS" => MAP_KEYS,
+    "JSON_PARSE" => JSON_PARSE,
+    "JSON_STRINGIFY" => JSON_STRINGIFY
 };
\ No newline at end of file
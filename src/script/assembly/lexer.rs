@@ -0,0 +1,234 @@
+use std::fmt::Display;
+
+const DIRECTIVES: [&str; 9] = ["named", "global", "use", "extern", "export", "define", "macro", "endmacro", "loc"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    /// A directive like `.global`, without the dot.
+    Directive(String),
+    /// A label declaration like `.loop:`, without the dot and colon.
+    Label(String),
+    /// A function declaration like `@main:`, without the `@` and colon.
+    Function(String),
+    Instruction(String),
+    /// Any other operand including its sigils, like `$x`, `&x`, `%0`, `10`, `97c` or `true`.
+    Operand(String),
+    /// A `#"..."` string literal with its escape sequences resolved.
+    String(String),
+    Char(char),
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Directive(d) => write!(f, ".{}", d),
+            Token::Label(l) => write!(f, ".{}:", l),
+            Token::Function(func) => write!(f, "@{}:", func),
+            Token::Instruction(s) | Token::Operand(s) => write!(f, "{}", s),
+            Token::String(s) => write!(f, "#\"{}\"", escape(s, '"')),
+            Token::Char(c) => write!(f, "'{}'", escape(&c.to_string(), '\'')),
+        }
+    }
+}
+
+/// The tokens of one line of MASM. `columns` holds the column of every token, starting at 1.
+#[derive(Debug, Clone)]
+pub struct Line {
+    pub number: usize,
+    pub tokens: Vec<Token>,
+    pub columns: Vec<usize>,
+}
+
+impl Display for Line {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let tokens = self.tokens.iter().map(|t| t.to_string()).collect::<Vec<_>>();
+        write!(f, "{}", tokens.join(" "))
+    }
+}
+
+/// Splits MASM into lines of tokens, leaving out empty lines and `;` comments. The first word of a line
+/// that is not a label or function declaration is its instruction or directive, every word after that is
+/// an operand. Errors are returned as `line:column: message`.
+pub fn lex(code: &str) -> Result<Vec<Line>, String> {
+    let mut lines = Vec::new();
+    for (i, text) in code.lines().enumerate() {
+        let chars = text.chars().collect::<Vec<_>>();
+        let mut line = Line {
+            number: i + 1,
+            tokens: Vec::new(),
+            columns: Vec::new(),
+        };
+        let mut operands = false;
+        let mut pos = 0;
+        while pos < chars.len() {
+            let c = chars[pos];
+            let column = pos + 1;
+            let at = |msg: &str| format!("{}:{}: {}", i + 1, column, msg);
+            if c.is_whitespace() {
+                pos += 1;
+                continue;
+            }
+            if c == ';' {
+                break;
+            }
+            let token = if c == '#' {
+                if chars.get(pos + 1) != Some(&'"') {
+                    return Err(at("Expected a string literal after '#'"));
+                }
+                pos += 2;
+                Token::String(quoted(&chars, &mut pos, '"').map_err(|e| at(&e))?)
+            }
+            else if c == '\'' {
+                pos += 1;
+                let s = quoted(&chars, &mut pos, '\'').map_err(|e| at(&e))?;
+                let mut s = s.chars();
+                match (s.next(), s.next()) {
+                    (Some(c), None) => Token::Char(c),
+                    _ => return Err(at("Character literals must contain exactly one character")),
+                }
+            }
+            else {
+                let start = pos;
+                while pos < chars.len() && !chars[pos].is_whitespace() && chars[pos] != ';' {
+                    pos += 1;
+                }
+                let word = chars[start..pos].iter().collect::<String>();
+                if operands {
+                    Token::Operand(word)
+                }
+                else if let Some(name) = word.strip_prefix('.') {
+                    if DIRECTIVES.contains(&name) {
+                        operands = true;
+                        Token::Directive(name.to_string())
+                    }
+                    else {
+                        Token::Label(name.strip_suffix(':').unwrap_or(name).to_string())
+                    }
+                }
+                else if let Some(name) = word.strip_prefix('@') {
+                    Token::Function(name.strip_suffix(':').unwrap_or(name).to_string())
+                }
+                else {
+                    operands = true;
+                    Token::Instruction(word)
+                }
+            };
+            operands |= matches!(token, Token::String(_) | Token::Char(_));
+            line.tokens.push(token);
+            line.columns.push(column);
+        }
+        if !line.tokens.is_empty() {
+            lines.push(line);
+        }
+    }
+    Ok(lines)
+}
+
+/// Reads a quoted literal up to the closing `quote`, with `pos` right after the opening quote.
+fn quoted(chars: &[char], pos: &mut usize, quote: char) -> Result<String, String> {
+    let mut buffer = String::new();
+    while let Some(&c) = chars.get(*pos) {
+        *pos += 1;
+        if c == quote {
+            return Ok(buffer);
+        }
+        if c != '\\' {
+            buffer.push(c);
+            continue;
+        }
+        let Some(&e) = chars.get(*pos) else {
+            break;
+        };
+        *pos += 1;
+        let digits = match e {
+            'x' => 2,
+            'u' => 4,
+            _ => 0,
+        };
+        if digits > 0 {
+            let hex = chars.get(*pos..*pos + digits).map(|h| h.iter().collect::<String>()).unwrap_or_default();
+            let Some(c) = u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32) else {
+                return Err(format!("Invalid escape sequence \\{}{}", e, hex));
+            };
+            *pos += digits;
+            buffer.push(c);
+            continue;
+        }
+        buffer.push(match e {
+            's' => ' ',
+            'n' => '\n',
+            'r' => '\r',
+            't' => '\t',
+            'b' => '\x08',
+            'f' => '\x0c',
+            'v' => '\x0b',
+            '0' => '\0',
+            c => c,
+        });
+    }
+    Err(format!("Unterminated {} literal", if quote == '"' { "string" } else { "character" }))
+}
+
+fn escape(s: &str, quote: char) -> String {
+    let mut buffer = String::new();
+    for c in s.chars() {
+        match c {
+            '\\' => buffer.push_str("\\\\"),
+            '\n' => buffer.push_str("\\n"),
+            '\r' => buffer.push_str("\\r"),
+            '\t' => buffer.push_str("\\t"),
+            c if c == quote => {
+                buffer.push('\\');
+                buffer.push(c);
+            }
+            c if c.is_control() => buffer.push_str(&format!("\\u{:04x}", c as u32)),
+            c => buffer.push(c),
+        }
+    }
+    buffer
+}
+
+/// Maps the lines of generated or linked code back to their source, following the `.loc <file> <line>`
+/// markers that state the source location of the line after them.
+pub struct Locator {
+    file: String,
+    line: usize,
+    origin: usize,
+}
+
+impl Locator {
+    pub fn new(file: &str) -> Self {
+        Self {
+            file: file.to_string(),
+            line: 1,
+            origin: 1,
+        }
+    }
+
+    /// Returns the source file and line of `line`, or `None` if it is a `.loc` marker.
+    pub fn locate(&mut self, line: &Line) -> Option<(String, usize)> {
+        if let [Token::Directive(d), file @ .., Token::Operand(number)] = &line.tokens[..] && d == "loc" {
+            self.file = file.iter().map(|t| t.to_string()).collect::<Vec<_>>().join(" ");
+            self.line = number.parse().unwrap_or(1);
+            self.origin = line.number + 1;
+            return None;
+        }
+        Some((self.file.clone(), self.line + line.number.saturating_sub(self.origin)))
+    }
+}
+
+/// The `.loc` marker for a line at `line` of `file`.
+pub fn loc(file: &str, line: usize) -> String {
+    format!(".loc {} {}\n", file, line)
+}
+
+/// Formats a source location for diagnostics. Line numbers of MVScript modules and libraries refer to the
+/// MASM generated for them, since MVScript lines are not tracked through code generation.
+pub fn position(file: &str, line: usize) -> String {
+    if file.ends_with(".mvs") || file.ends_with(".mvl") {
+        format!("{} (line {} of the generated MASM)", file, line)
+    }
+    else {
+        format!("{}:{}", file, line)
+    }
+}
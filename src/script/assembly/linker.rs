@@ -1,15 +1,19 @@
+use std::env;
 use std::fs::OpenOptions;
 use std::io::Read;
+use std::path::PathBuf;
 use std::process::exit;
 use hashbrown::HashSet;
-use mvutils::utils::remove_quotes;
-use crate::script::assembly::assembler::{extract};
-use crate::script::compiler::codegen::Generator;
-use crate::script::compiler::lexer::Lexer;
-use crate::script::compiler::parser::Parser;
+use crate::script::assembly::consts::BUILTIN_FUNCTIONS;
+use crate::script::assembly::lexer::{loc, position, Token};
+use crate::script::assembly::object::{Library, Target};
+use crate::script::cache;
 
 pub struct AssemblyFile {
     pub name: String,
+    pub dir: String,
+    /// The file the module was loaded from, used in diagnostics.
+    pub path: String,
     pub code: String
 }
 
@@ -19,12 +23,27 @@ fn err(str: String) -> ! {
 }
 
 
-pub fn link(mut files: Vec<AssemblyFile>) -> String {
-    let mut names = files.iter().map(|f| f.name.clone()).collect::<Vec<_>>();
-    names.sort_unstable();
+pub fn link(files: Vec<AssemblyFile>, include: &[String], optimize: bool) -> String {
+    let modules = files.into_iter().map(|f| {
+        let library = Library::new(f.name, &f.path, &f.code).unwrap_or_else(|e| err(e));
+        (library, f.dir)
+    }).collect();
+    link_objects(modules, include, optimize)
+}
+
+/// Links module objects together with the modules they include. Every module is given together with the
+/// directory its externs are searched in first, and the first module is the entry point.
+pub fn link_objects(modules: Vec<(Library, String)>, include: &[String], optimize: bool) -> String {
+    let mut loaded = modules.iter().map(|(l, _)| l.name.clone()).collect::<Vec<_>>();
+    let mut dependencies = Vec::new();
+    for (library, dir) in modules.iter() {
+        let mut chain = vec![library.name.clone()];
+        resolve(library, dir, &mut chain, &mut loaded, &mut dependencies, include, optimize);
+    }
+    let libraries = modules.into_iter().map(|(l, _)| l).chain(dependencies).collect::<Vec<_>>();
+
     let mut adapted = HashSet::new();
-    for name in names.iter() {
-        let name = adapt(name.clone());
+    for name in libraries.iter().map(|l| adapt(l.name.clone())) {
         if adapted.contains(&name) {
             err(format!("Duplicate adapted file name: \"{}\"!\nMake sure that the files have unique names when excluding special characters ('.', '/', '\\').", name));
         }
@@ -32,219 +51,213 @@ pub fn link(mut files: Vec<AssemblyFile>) -> String {
     }
     drop(adapted);
 
-    let externals_needed = files.iter().map(|f| {
-        let (_, _, externs, _) = extract(&f.code);
-        let mut needed = Vec::new();
-        for e in externs {
-            if names.binary_search(&e).is_err() {
-                needed.push(e);
-            }
+    let modules = libraries.iter().map(Module::new).collect::<Vec<_>>();
+
+    let mut bases = Vec::new();
+    let mut base = 0;
+    for library in &libraries {
+        bases.push(base);
+        base += library.slots;
+    }
+    let named = libraries.iter().any(|l| l.named);
+    let mut builtins = Vec::new();
+
+    let mut linked = libraries.iter().enumerate().map(|(i, library)| {
+        let name = &modules[i].adapted;
+        let mut out = String::new();
+        if i == 0 && named {
+            out.push_str(".named\n");
         }
-        needed
-    }).flatten().collect::<HashSet<_>>();
-
-    for external in externals_needed {
-        const PATHS: [&str; 6] = ["/usr/bin/", "/usr/lib/", "/usr/include/", "/usr/local/bin/", "/usr/local/lib/", "/usr/local/include/"];
-        let asm = external.clone() + ".masm";
-        let mvs = external.clone() + ".mvs";
-        if let Some(mut file) = PATHS.iter().flat_map(|s| {
-            let path = s.to_string() + &asm;
-            OpenOptions::new().read(true).open(path).ok()
-        }).next() {
-            let mut code = String::new();
-            file.read_to_string(&mut code).expect("Failed to read dependency");
-
-            files.push(AssemblyFile {
-                name: external,
-                code,
-            })
-        } else if let Some(mut file) = PATHS.iter().flat_map(|s| {
-            let path = s.to_string() + &mvs;
-            OpenOptions::new().read(true).open(path).ok()
-        }).next() {
-            let mut code = String::new();
-            file.read_to_string(&mut code).expect("Failed to read dependency");
-            let lexer = Lexer::new(code);
-
-            let parser = Parser::new(lexer);
-
-            let result = parser.parse();
-
-            if let Err(e) = result {
-                println!("{:?}", e);
-                exit(1);
+        let mut relocations = library.relocations.iter().peekable();
+        let mut last = None;
+
+        for (l, line) in library.lines.iter().enumerate() {
+            let source = &library.files[line.file];
+            let mut tokens = line.tokens.clone();
+            while let Some(relocation) = relocations.next_if(|r| r.line == l) {
+                let text = match &relocation.target {
+                    Target::Slot { prefix, slot } => format!("{}{}", prefix, slot + bases[i]),
+                    Target::Local { prefix, ident } => format!("{}{}_{}", prefix, name, ident),
+                    Target::Function(function) => qualify(&modules[i], i, function),
+                    Target::Call(target) => {
+                        let call = resolve_call(&modules, &library.usages, i, target).unwrap_or_else(|e| err(format!("{}: {}", position(source, line.line), e)));
+                        if BUILTIN_FUNCTIONS.contains_key(call.to_ascii_uppercase().as_str()) && !builtins.contains(&call) {
+                            builtins.push(call.clone());
+                        }
+                        call
+                    }
+                };
+                match &mut tokens[relocation.token] {
+                    Token::Label(s) | Token::Function(s) | Token::Operand(s) => *s = text,
+                    token => err(format!("{}: Cannot relocate {}", position(source, line.line), token)),
+                }
+            }
+            if last != Some((line.file, line.line.wrapping_sub(1))) {
+                out.push_str(&loc(source, line.line));
             }
-            let result = result.unwrap();
+            out.push_str(&tokens.iter().map(|t| t.to_string()).collect::<Vec<_>>().join(" "));
+            out.push('\n');
+            last = Some((line.file, line.line));
+        }
 
-            let generator = Generator::new(result);
+        out
+    }).collect::<String>();
 
-            let script = generator.generate();
+    let uses = builtins.iter().map(|b| format!(".use {}\n", b)).collect::<String>();
+    linked.insert_str(if named { ".named\n".len() } else { 0 }, &uses);
+    linked
+}
 
-            files.push(AssemblyFile {
-                name: external,
-                code: script,
-            })
-        } else {
-            err(format!("External dependency '{}' not present!", external));
-        }
-    }
+struct Module {
+    name: String,
+    adapted: String,
+    functions: Vec<String>,
+    exports: Vec<String>,
+}
 
-    files.into_iter().map(|f| {
-        let input = remove_quotes(&clean(f.code.trim()));
-        (f.name, input.split_whitespace().collect::<Vec<_>>().join(" "))
-    }).enumerate().map(|(i, (name, s))| {
-        if !s.starts_with(".named") {
-            err("Files that are linked are not allowed to be index-accessed. Use '.named' instead.".to_string());
+impl Module {
+    fn new(library: &Library) -> Self {
+        Self {
+            name: library.name.clone(),
+            adapted: adapt(library.name.clone()),
+            functions: library.functions.clone(),
+            exports: library.exports.clone(),
         }
+    }
+}
 
-        let (globals, _, _, labels) = extract(&s);
+/// Every function is namespaced with its module, apart from the entry point in the first file.
+fn qualify(module: &Module, index: usize, function: &str) -> String {
+    if function == "static" {
+        format!("{}_static", module.adapted)
+    }
+    else if index == 0 && function == "main" {
+        function.to_string()
+    }
+    else {
+        format!("{}.{}", module.adapted, function)
+    }
+}
 
-        let name = adapt(name);
-        let mut code = String::new();
-        if i == 0 {
-            code.push_str(".named ");
+/// Resolves `call module.function` or a plain `call function`. Plain calls prefer the calling module,
+/// then the library functions it imports with `.use`, then the single module exporting the function and
+/// finally the library function of that name.
+fn resolve_call(modules: &[Module], usages: &[String], index: usize, target: &str) -> Result<String, String> {
+    if let Some((module, function)) = target.rsplit_once('.') {
+        let Some(i) = modules.iter().position(|m| m.name == module || m.adapted == module) else {
+            return Err(format!("Call to unknown module \"{}\" in \"{}\"!", module, target));
+        };
+        if !modules[i].functions.iter().any(|f| f == function) {
+            return Err(format!("Module \"{}\" has no function \"{}\"!", module, function));
         }
-        let mut tokens = s.split_whitespace();
-
-        while let Some(mut token) = tokens.next() {
-            if token == ".named" {
-                continue;
-            }
-            if token.starts_with('@') {
-                let mut ident = token.split_at(1).1;
-                if token.ends_with(':') {
-                    ident = ident.split_at(ident.len() - 1).0;
-                }
-                if ident == "static" {
-                    code.push_str(&format!("@{}_static:", name));
-                    code.push(' ');
-                    continue;
-                }
-            }
-            if token.starts_with('.') {
-                let mut ident = token.split_at(1).1;
-                if token.ends_with(':') {
-                    ident = ident.split_at(ident.len() - 1).0;
-                }
-                if labels.contains(&ident.to_string()) {
-                    code.push_str(&format!(".{}_{}:", name, ident));
-                }
-                else {
-                    code.push_str(token);
-                }
-                code.push(' ');
-                continue;
-            }
-            if token.starts_with('%') {
-                token = token.split_at(1).1;
-                code.push('%');
-            }
-            if token.starts_with(['$', '&', '*']) {
-                let (c, ident) = token.split_at(1);
-                if globals.contains(&ident.to_string()) {
-                    code.push(c.chars().next().unwrap());
-                    code.push_str(&format!("{}_{}", name, ident));
-                }
-                else {
-                    code.push_str(token);
-                }
-                code.push(' ');
-                continue;
-            }
-            if token == "static" {
-                code.push_str(&format!("{}_static", name));
-                code.push(' ');
-                continue;
-            }
-            let first = token.chars().next().unwrap();
-            if (first.is_ascii_alphabetic() || first == '_') && globals.contains(&token.to_string()) {
-                code.push_str(&format!("{}_{}", name, token));
-                code.push(' ');
-                continue;
+        if i != index && !modules[i].exports.iter().any(|f| f == function) {
+            return Err(format!("Function \"{}\" in module \"{}\" is private! Export it with '.export {}' or 'pub fn'.", function, module, function));
+        }
+        return Ok(qualify(&modules[i], i, function));
+    }
+    if target == "static" || modules[index].functions.iter().any(|f| f == target) {
+        return Ok(qualify(&modules[index], index, target));
+    }
+    if usages.contains(&target.to_ascii_uppercase()) {
+        return Ok(target.to_string());
+    }
+    let exporting = modules.iter().enumerate().filter(|(_, m)| m.exports.iter().any(|f| f == target)).collect::<Vec<_>>();
+    match exporting.as_slice() {
+        [(i, m)] => Ok(qualify(m, *i, target)),
+        [] => {
+            if BUILTIN_FUNCTIONS.contains_key(target.to_ascii_uppercase().as_str()) {
+                return Ok(target.to_string());
             }
-            code.push_str(token);
-            code.push(' ');
-            match token.to_ascii_uppercase().as_str() {
-                "JMP" | "JE" | "JNE" | "JL" | "JLE" | "JG" | "JGE" => {
-                    let to = tokens.next().unwrap();
-                    if labels.contains(&to.to_string()) {
-                        code.push_str(&format!("{}_{}", name, to));
-                    }
-                    else {
-                        code.push_str(to);
-                    }
-                    code.push(' ');
-                }
-                "JZ" | "JNZ" | "JN" | "JNN" => {
-                    let mut token = tokens.next().unwrap();
-                    if token.starts_with('%') {
-                        token = token.split_at(1).1;
-                        code.push('%');
-                    }
-                    if token.starts_with(['$', '&', '*']) {
-                        let (c, ident) = token.split_at(1);
-                        if globals.contains(&ident.to_string()) {
-                            code.push(c.chars().next().unwrap());
-                            code.push_str(&format!("{}_{}", name, ident));
-                        }
-                        else {
-                            code.push_str(token);
-                        }
-                        code.push(' ');
-                    }
-                    code.push(' ');
-                    let to = tokens.next().unwrap();
-                    if labels.contains(&to.to_string()) {
-                        code.push_str(&format!("{}_{}", name, to));
-                    }
-                    else {
-                        code.push_str(to);
-                    }
-                    code.push(' ');
-                }
-                _ => {}
+            if let Some(m) = modules.iter().find(|m| m.functions.iter().any(|f| f == target)) {
+                return Err(format!("Function \"{}\" in module \"{}\" is private! Export it with '.export {}' or 'pub fn'.", target, m.name, target));
             }
+            Err(format!("Call to unknown function \"{}\"!", target))
+        }
+        _ => {
+            let names = exporting.iter().map(|(_, m)| m.name.clone()).collect::<Vec<_>>().join(", ");
+            Err(format!("Ambiguous call to \"{}\", it is exported by modules: {}. Use a qualified call like 'call {}.{}'.", target, names, exporting[0].1.name, target))
         }
+    }
+}
 
-        code
-    }).collect()
+/// Loads the externs of `library` depth first, appending every module after its own dependencies so the
+/// resulting order only depends on the order of the `.extern` lines. `dir` is the directory `library` was
+/// loaded from, and `chain` holds the modules currently being resolved and is used to report include
+/// cycles. `.mvs` dependencies are compiled with `optimize`.
+fn resolve(library: &Library, dir: &str, chain: &mut Vec<String>, loaded: &mut Vec<String>, out: &mut Vec<Library>, include: &[String], optimize: bool) {
+    for external in &library.externs {
+        if chain.contains(external) {
+            err(format!("Include cycle detected: {} -> {}", chain.join(" -> "), external));
+        }
+        if loaded.contains(external) {
+            continue;
+        }
+        loaded.push(external.clone());
+        let (module, dir) = load(external.clone(), dir, include, optimize);
+        chain.push(external.clone());
+        resolve(&module, &dir, chain, loaded, out, include, optimize);
+        chain.pop();
+        out.push(module);
+    }
 }
 
-fn clean(s: &str) -> String {
-    s.lines().filter_map(|s| {
-        let s = s.trim();
-        if s.is_empty() || s.starts_with(';') {
-            None
+/// Finds the extern `external` and returns its object together with the directory it was found in.
+/// Libraries are used as they are, source modules are compiled into an object first.
+fn load(external: String, dir: &str, include: &[String], optimize: bool) -> (Library, String) {
+    let (path, bytes) = match find_module(&external, &search_paths(dir, include)) {
+        Ok(found) => found,
+        Err(tried) => {
+            let tried = tried.iter().map(|p| format!("  {}", p.display())).collect::<Vec<_>>().join("\n");
+            err(format!("External dependency '{}' not present! Searched:\n{}", external, tried));
         }
-        else if s.contains(';') {
-            let mut buffer = String::new();
-            let mut str = false;
-            for token in s.split(';') {
-                buffer.push_str(token);
-                if token.ends_with('\'') {
-                    buffer.push(';');
-                    continue;
-                }
-                if token.chars().filter(|c| *c == '"').count() % 2 == 1 {
-                    str = !str;
-                }
-                if str {
-                    buffer.push(';');
-                    continue;
-                }
-                else {
-                    break;
+    };
+    let dir = path.parent().map(|p| p.to_string_lossy().to_string()).unwrap_or_default();
+    let display = path.to_string_lossy().to_string();
+
+    if path.extension().is_some_and(|e| e == "mvl") {
+        let library = Library::from_bytes(&bytes).unwrap_or_else(|e| err(format!("Failed to load library {}: {}", display, e)));
+        if library.name != external {
+            err(format!("Library {} contains module \"{}\", expected \"{}\"!", display, library.name, external));
+        }
+        return (library, dir);
+    }
+
+    let mut code = String::from_utf8_lossy(&bytes).to_string();
+    if path.extension().is_some_and(|e| e == "mvs") {
+        code = cache::compile(code, optimize);
+    }
+    (Library::new(external, &display, &code).unwrap_or_else(|e| err(e)), dir)
+}
+
+/// Directories searched for an extern, in order: the including file's directory,
+/// `MVC_PATH`, the `-I` flags and finally the system wide install locations.
+pub fn search_paths(dir: &str, include: &[String]) -> Vec<PathBuf> {
+    const PATHS: [&str; 6] = ["/usr/bin/", "/usr/lib/", "/usr/include/", "/usr/local/bin/", "/usr/local/lib/", "/usr/local/include/"];
+    let mut paths = vec![PathBuf::from(dir)];
+    if let Some(env) = env::var_os("MVC_PATH") {
+        paths.extend(env::split_paths(&env));
+    }
+    paths.extend(include.iter().map(PathBuf::from));
+    paths.extend(PATHS.iter().map(PathBuf::from));
+    paths
+}
+
+fn find_module(name: &str, paths: &[PathBuf]) -> Result<(PathBuf, Vec<u8>), Vec<PathBuf>> {
+    let mut tried = Vec::new();
+    for dir in paths {
+        for ext in ["masm", "mvs", "mvl"] {
+            let path = dir.join(format!("{}.{}", name, ext));
+            if let Ok(mut file) = OpenOptions::new().read(true).open(&path) {
+                let mut bytes = Vec::new();
+                if let Err(e) = file.read_to_end(&mut bytes) {
+                    err(format!("Failed to read dependency {}: {}", path.display(), e));
                 }
+                return Ok((path, bytes));
             }
-            buffer.push('\n');
-            Some(buffer)
+            tried.push(path);
         }
-        else {
-            let mut s = s.to_string();
-            s.push('\n');
-            Some(s)
-        }
-    }).collect()
+    }
+    Err(tried)
 }
 
 fn adapt(mut s: String) -> String {
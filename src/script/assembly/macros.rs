@@ -0,0 +1,152 @@
+use std::process::exit;
+use hashbrown::HashMap;
+use crate::script::assembly::lexer::{lex, loc, position, Token};
+
+fn err(str: String) -> ! {
+    eprintln!("{}", str);
+    exit(1);
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Vec<String>>,
+    labels: Vec<String>,
+}
+
+/// Expands `.define` constants and `.macro` blocks of one module. Definitions may appear anywhere in the
+/// module and are removed from the output. Labels declared inside a macro are renamed on every expansion
+/// so a macro can be used multiple times within one function. A `.loc` line is inserted wherever the
+/// output no longer follows the lines of `code`, so every instruction keeps its original line number.
+/// `path` is the file the module was loaded from.
+pub fn expand(path: &str, code: &str) -> String {
+    let mut defines = HashMap::new();
+    let mut macros = HashMap::new();
+    let mut lines = Vec::new();
+    let mut current: Option<(String, usize, Macro)> = None;
+
+    let code = lex(code).unwrap_or_else(|e| err(format!("{}:{}", path, e)));
+    for line in code {
+        let at = |msg: String| -> ! { err(format!("{}: {}", position(path, line.number), msg)) };
+        let tokens = line.tokens.iter().map(|t| t.to_string()).collect::<Vec<_>>();
+        match tokens[0].as_str() {
+            ".define" => {
+                if current.is_some() {
+                    at(format!("Constants cannot be defined inside a macro: {}", line));
+                }
+                if tokens.len() != 3 {
+                    at(format!(".define must be followed by a name and a value: {}", line));
+                }
+                if defines.insert(tokens[1].clone(), tokens[2].clone()).is_some() {
+                    at(format!("Duplicate constant \"{}\"!", tokens[1]));
+                }
+            }
+            ".macro" => {
+                if let Some((name, _, _)) = &current {
+                    at(format!("Macros cannot be defined inside other macros, found .macro inside \"{}\"!", name));
+                }
+                if tokens.len() < 2 {
+                    at(".macro must be followed by a name!".to_string());
+                }
+                let params = tokens[2..].to_vec();
+                if let Some(p) = params.iter().enumerate().find(|(i, p)| params[..*i].contains(p)).map(|(_, p)| p) {
+                    at(format!("Duplicate parameter \"{}\" in macro \"{}\"!", p, tokens[1]));
+                }
+                current = Some((tokens[1].clone(), line.number, Macro {
+                    params,
+                    body: Vec::new(),
+                    labels: Vec::new(),
+                }));
+            }
+            ".endmacro" => {
+                let Some((name, _, m)) = current.take() else {
+                    at(".endmacro without matching .macro!".to_string());
+                };
+                if macros.insert(name.clone(), m).is_some() {
+                    at(format!("Duplicate macro \"{}\"!", name));
+                }
+            }
+            _ => {
+                if let Some((_, _, m)) = &mut current {
+                    if let Some(Token::Label(label)) = line.tokens.first() {
+                        m.labels.push(label.clone());
+                    }
+                    m.body.push(tokens);
+                }
+                else {
+                    lines.push((line.number, tokens));
+                }
+            }
+        }
+    }
+    if let Some((macro_name, line, _)) = current {
+        err(format!("{}: Macro \"{}\" is missing .endmacro!", position(path, line), macro_name));
+    }
+
+    let mut out = String::new();
+    let mut expansions = 0;
+    let mut next = 0;
+    for (line, tokens) in lines {
+        let mut expanded = Vec::new();
+        let at = position(path, line);
+        expand_line(tokens, &defines, &macros, &mut Vec::new(), &mut expansions, &mut expanded, &at);
+        for code in expanded {
+            if line != next {
+                out.push_str(&loc(path, line));
+            }
+            out.push_str(&code);
+            out.push('\n');
+            next = line + 1;
+        }
+    }
+    out
+}
+
+fn expand_line(tokens: Vec<String>, defines: &HashMap<String, String>, macros: &HashMap<String, Macro>, stack: &mut Vec<String>, expansions: &mut u32, out: &mut Vec<String>, at: &str) {
+    let tokens = tokens.into_iter().map(|t| defines.get(&t).cloned().unwrap_or(t)).collect::<Vec<_>>();
+    let Some(m) = macros.get(&tokens[0]) else {
+        out.push(tokens.join(" "));
+        return;
+    };
+    let name = &tokens[0];
+    if stack.contains(name) {
+        err(format!("{}: Recursive macro expansion: {} -> {}", at, stack.join(" -> "), name));
+    }
+    let args = &tokens[1..];
+    if args.len() != m.params.len() {
+        err(format!("{}: Macro \"{}\" expects {} arguments, got {}: {}", at, name, m.params.len(), args.len(), tokens.join(" ")));
+    }
+    *expansions += 1;
+    let id = *expansions;
+    stack.push(name.clone());
+    for line in &m.body {
+        let instruction = line.iter().find(|t| !(t.starts_with('.') && t.ends_with(':')));
+        let jump = instruction.is_some_and(|t| t.to_ascii_lowercase().starts_with('j'));
+        let line = line.iter().map(|t| substitute(t, jump, m, args, name, id)).collect();
+        expand_line(line, defines, macros, stack, expansions, out, at);
+    }
+    stack.pop();
+}
+
+/// A parameter is replaced by its argument when it makes up the whole token, or only its name after any
+/// `%`, `$`, `&` and `*` sigils, in which case the sigils of the argument are dropped. Labels of the macro
+/// are renamed where they are declared and where they are used as jump targets.
+fn substitute(token: &str, jump: bool, m: &Macro, args: &[String], name: &str, id: u32) -> String {
+    if let Some(i) = m.params.iter().position(|p| p == token) {
+        return args[i].clone();
+    }
+    let ident = token.trim_start_matches(['%', '$', '&', '*']);
+    let sigils = &token[..token.len() - ident.len()];
+    if !sigils.is_empty() && let Some(i) = m.params.iter().position(|p| p == ident) {
+        return format!("{}{}", sigils, args[i].trim_start_matches(['%', '$', '&', '*']));
+    }
+    if let Some(label) = token.strip_prefix('.') {
+        let label = label.trim_end_matches(':');
+        if m.labels.iter().any(|l| l == label) {
+            return format!(".{}__{}{}:", label, name, id);
+        }
+    }
+    else if jump && m.labels.iter().any(|l| l == token) {
+        return format!("{}__{}{}", token, name, id);
+    }
+    token.to_string()
+}
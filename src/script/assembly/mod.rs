@@ -1,3 +1,7 @@
 pub mod assembler;
 pub mod consts;
-pub mod linker;
\ No newline at end of file
+pub mod lexer;
+pub mod linker;
+pub mod macros;
+pub mod object;
+pub mod peephole;
\ No newline at end of file
@@ -0,0 +1,463 @@
+use bytebuffer::ByteBuffer;
+use mvutils::save::{Loader, Saver};
+use crate::script::assembly::assembler::{extract, variables};
+use crate::script::assembly::lexer::{lex, position, Line, Locator, Token};
+use crate::script::assembly::macros::expand;
+
+const MAGIC: u32 = 0x4D564C00;
+const VERSION: u16 = 3;
+
+/// A module assembled into a relocatable object. Its lines are stored as tokens with macros expanded, and
+/// every token that depends on where the module ends up in the program is listed as a relocation, so the
+/// linker only patches those tokens instead of lexing and analysing the code again. `.mvl` library files
+/// are serialized objects, and the linker builds the same object for `.masm` and `.mvs` modules.
+pub struct Library {
+    /// The module name, which prefixes the symbols of the module and must match the name it is loaded as.
+    pub name: String,
+    /// Whether the module addresses its variables by name with `.named`.
+    pub named: bool,
+    pub functions: Vec<String>,
+    pub exports: Vec<String>,
+    pub globals: Vec<String>,
+    /// The modules imported with `.extern`.
+    pub externs: Vec<String>,
+    /// The library functions imported with `.use`, in upper case.
+    pub usages: Vec<String>,
+    /// The number of numbered variable slots the module uses.
+    pub slots: u32,
+    /// The source files the lines were generated from.
+    pub files: Vec<String>,
+    pub lines: Vec<SourceLine>,
+    /// The relocations of the module, ordered by line and token.
+    pub relocations: Vec<Relocation>,
+}
+
+/// A line of a module with the source location it was generated from.
+pub struct SourceLine {
+    /// The index of the source file in `Library::files`.
+    pub file: usize,
+    pub line: usize,
+    pub tokens: Vec<Token>,
+}
+
+/// A token that is rewritten when the module is linked.
+pub struct Relocation {
+    pub line: usize,
+    pub token: usize,
+    pub target: Target,
+}
+
+pub enum Target {
+    /// A numbered variable slot, moved past the slots of the modules linked before this one. `prefix`
+    /// holds the sigils of the operand.
+    Slot { prefix: String, slot: u32 },
+    /// A global, a label or the static function, prefixed with the module name.
+    Local { prefix: String, ident: String },
+    /// A function declaration, namespaced with the module name.
+    Function(String),
+    /// The target of a call, resolved against the functions of all linked modules.
+    Call(String),
+}
+
+impl Library {
+    /// Expands and lexes the MASM of module `name`, loaded from `path`, and collects its symbols and
+    /// relocations. Fails if the module declares a function twice, exports an unknown function or uses a
+    /// named variable without `.named`.
+    pub fn new(name: String, path: &str, code: &str) -> Result<Self, String> {
+        let code = expand(path, code);
+        let lines = lex(&code).map_err(|e| format!("{}:{}", path, e))?;
+        let (globals, usages, externs, labels) = extract(&code);
+        let named = is_named(&lines);
+        let mut library = Self {
+            name,
+            named,
+            functions: Vec::new(),
+            exports: Vec::new(),
+            globals,
+            externs,
+            usages,
+            slots: 0,
+            files: Vec::new(),
+            lines: Vec::new(),
+            relocations: Vec::new(),
+        };
+
+        let mut locator = Locator::new(path);
+        for line in lines {
+            let Some((source, number)) = locator.locate(&line) else {
+                continue;
+            };
+            let at = |msg: String| format!("{}: {}", position(&source, number), msg);
+            let index = library.lines.len();
+
+            let mut slots = Vec::new();
+            for (j, variable) in variables(&line) {
+                if let Ok(slot) = variable.parse::<u32>() {
+                    library.slots = library.slots.max(slot + 1);
+                    slots.push((j, variable.len(), slot));
+                }
+                else if !named && !library.globals.iter().any(|g| g == variable) {
+                    return Err(at(format!("Variable \"{}\" must be a slot number, module \"{}\" does not use '.named'", variable, library.name)));
+                }
+            }
+
+            let mut instruction = String::new();
+            let mut operand = 0;
+            let mut tokens = Vec::new();
+            for (j, token) in line.tokens.iter().enumerate() {
+                let slot = slots.iter().find(|(k, _, _)| *k == j);
+                let target = if let (Token::Operand(o), Some((_, len, slot))) = (token, slot) {
+                    operand += 1;
+                    Some(Target::Slot { prefix: o[..o.len() - len].to_string(), slot: *slot })
+                }
+                else {
+                    match token {
+                        Token::Directive(d) if d == "export" => {
+                            let Some(Token::Operand(ident)) = line.tokens.get(j + 1) else {
+                                return Err(at(".export must be followed by a function name!".to_string()));
+                            };
+                            if library.exports.contains(ident) {
+                                return Err(format!("Duplicate export \"{}\" in module \"{}\"!", ident, library.name));
+                            }
+                            library.exports.push(ident.clone());
+                            break;
+                        }
+                        Token::Directive(d) if d == "named" => break,
+                        Token::Function(function) => {
+                            if library.functions.contains(function) {
+                                return Err(format!("Duplicate function \"{}\" in module \"{}\"!", function, library.name));
+                            }
+                            library.functions.push(function.clone());
+                            Some(Target::Function(function.clone()))
+                        }
+                        Token::Label(label) if labels.contains(label) => Some(Target::Local { prefix: String::new(), ident: label.clone() }),
+                        Token::Instruction(op) => {
+                            instruction = op.to_ascii_uppercase();
+                            None
+                        }
+                        Token::Operand(o) => {
+                            operand += 1;
+                            match (instruction.as_str(), operand) {
+                                ("CALL", 1) => Some(Target::Call(o.clone())),
+                                ("JMP" | "JE" | "JNE" | "JL" | "JLE" | "JG" | "JGE", 1) | ("JZ" | "JNZ" | "JN" | "JNN", 2) => {
+                                    labels.contains(o).then(|| Target::Local { prefix: String::new(), ident: o.clone() })
+                                }
+                                _ => local(o, &library.globals),
+                            }
+                        }
+                        _ => None,
+                    }
+                };
+                if let Some(target) = target {
+                    library.relocations.push(Relocation {
+                        line: index,
+                        token: tokens.len(),
+                        target,
+                    });
+                }
+                tokens.push(token.clone());
+            }
+            if tokens.is_empty() {
+                continue;
+            }
+            let file = match library.files.iter().position(|f| *f == source) {
+                Some(file) => file,
+                None => {
+                    library.files.push(source);
+                    library.files.len() - 1
+                }
+            };
+            library.lines.push(SourceLine {
+                file,
+                line: number,
+                tokens,
+            });
+        }
+        if let Some(missing) = library.exports.iter().find(|e| !library.functions.contains(e)) {
+            return Err(format!("Module \"{}\" exports unknown function \"{}\"!", library.name, missing));
+        }
+        Ok(library)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buffer = ByteBuffer::new();
+        buffer.push_u32(MAGIC);
+        buffer.push_u16(VERSION);
+        buffer.push_string(&self.name);
+        buffer.push_u8(self.named as u8);
+        for list in [&self.functions, &self.exports, &self.globals, &self.externs, &self.usages, &self.files] {
+            push_strings(&mut buffer, list);
+        }
+        buffer.push_u32(self.slots);
+        buffer.push_u32(self.lines.len() as u32);
+        for line in &self.lines {
+            buffer.push_u32(line.file as u32);
+            buffer.push_u32(line.line as u32);
+            buffer.push_u32(line.tokens.len() as u32);
+            for token in &line.tokens {
+                push_token(&mut buffer, token);
+            }
+        }
+        buffer.push_u32(self.relocations.len() as u32);
+        for relocation in &self.relocations {
+            buffer.push_u32(relocation.line as u32);
+            buffer.push_u32(relocation.token as u32);
+            match &relocation.target {
+                Target::Slot { prefix, slot } => {
+                    buffer.push_u8(0);
+                    buffer.push_string(prefix);
+                    buffer.push_u32(*slot);
+                }
+                Target::Local { prefix, ident } => {
+                    buffer.push_u8(1);
+                    buffer.push_string(prefix);
+                    buffer.push_string(ident);
+                }
+                Target::Function(function) => {
+                    buffer.push_u8(2);
+                    buffer.push_string(function);
+                }
+                Target::Call(call) => {
+                    buffer.push_u8(3);
+                    buffer.push_string(call);
+                }
+            }
+        }
+        buffer.into_vec()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let mut buffer = ByteBuffer::from_bytes(bytes);
+        if buffer.pop_u32() != Some(MAGIC) {
+            return Err("Not a library file".to_string());
+        }
+        let version = buffer.pop_u16().ok_or("Truncated library header")?;
+        if version != VERSION {
+            return Err(format!("Unsupported library version {}, expected {}", version, VERSION));
+        }
+        let library = read(&mut buffer).ok_or("Truncated library")?;
+        if let Some(relocation) = library.relocations.iter().find(|r| library.lines.get(r.line).is_none_or(|l| r.token >= l.tokens.len())) {
+            return Err(format!("Relocation of token {} in line {} is out of range", relocation.token, relocation.line));
+        }
+        if library.lines.iter().any(|l| l.file >= library.files.len()) {
+            return Err("Line refers to an unknown source file".to_string());
+        }
+        Ok(library)
+    }
+}
+
+fn read(buffer: &mut ByteBuffer) -> Option<Library> {
+    let name = buffer.pop_string()?;
+    let named = buffer.pop_u8()? != 0;
+    let functions = pop_strings(buffer)?;
+    let exports = pop_strings(buffer)?;
+    let globals = pop_strings(buffer)?;
+    let externs = pop_strings(buffer)?;
+    let usages = pop_strings(buffer)?;
+    let files = pop_strings(buffer)?;
+    let slots = buffer.pop_u32()?;
+    let mut lines = Vec::new();
+    for _ in 0..buffer.pop_u32()? {
+        let file = buffer.pop_u32()? as usize;
+        let line = buffer.pop_u32()? as usize;
+        let tokens = (0..buffer.pop_u32()?).map(|_| pop_token(buffer)).collect::<Option<Vec<_>>>()?;
+        lines.push(SourceLine {
+            file,
+            line,
+            tokens,
+        });
+    }
+    let mut relocations = Vec::new();
+    for _ in 0..buffer.pop_u32()? {
+        let line = buffer.pop_u32()? as usize;
+        let token = buffer.pop_u32()? as usize;
+        let target = match buffer.pop_u8()? {
+            0 => Target::Slot { prefix: buffer.pop_string()?, slot: buffer.pop_u32()? },
+            1 => Target::Local { prefix: buffer.pop_string()?, ident: buffer.pop_string()? },
+            2 => Target::Function(buffer.pop_string()?),
+            3 => Target::Call(buffer.pop_string()?),
+            _ => return None,
+        };
+        relocations.push(Relocation {
+            line,
+            token,
+            target,
+        });
+    }
+    Some(Library {
+        name,
+        named,
+        functions,
+        exports,
+        globals,
+        externs,
+        usages,
+        slots,
+        files,
+        lines,
+        relocations,
+    })
+}
+
+fn push_strings(buffer: &mut ByteBuffer, strings: &[String]) {
+    buffer.push_u32(strings.len() as u32);
+    for s in strings {
+        buffer.push_string(s);
+    }
+}
+
+fn pop_strings(buffer: &mut ByteBuffer) -> Option<Vec<String>> {
+    (0..buffer.pop_u32()?).map(|_| buffer.pop_string()).collect()
+}
+
+fn push_token(buffer: &mut ByteBuffer, token: &Token) {
+    match token {
+        Token::Directive(s) => {
+            buffer.push_u8(0);
+            buffer.push_string(s);
+        }
+        Token::Label(s) => {
+            buffer.push_u8(1);
+            buffer.push_string(s);
+        }
+        Token::Function(s) => {
+            buffer.push_u8(2);
+            buffer.push_string(s);
+        }
+        Token::Instruction(s) => {
+            buffer.push_u8(3);
+            buffer.push_string(s);
+        }
+        Token::Operand(s) => {
+            buffer.push_u8(4);
+            buffer.push_string(s);
+        }
+        Token::String(s) => {
+            buffer.push_u8(5);
+            buffer.push_string(s);
+        }
+        Token::Char(c) => {
+            buffer.push_u8(6);
+            buffer.push_u32(*c as u32);
+        }
+    }
+}
+
+fn pop_token(buffer: &mut ByteBuffer) -> Option<Token> {
+    Some(match buffer.pop_u8()? {
+        0 => Token::Directive(buffer.pop_string()?),
+        1 => Token::Label(buffer.pop_string()?),
+        2 => Token::Function(buffer.pop_string()?),
+        3 => Token::Instruction(buffer.pop_string()?),
+        4 => Token::Operand(buffer.pop_string()?),
+        5 => Token::String(buffer.pop_string()?),
+        6 => Token::Char(char::from_u32(buffer.pop_u32()?)?),
+        _ => return None,
+    })
+}
+
+fn is_named(lines: &[Line]) -> bool {
+    let first = lines.iter().find(|l| !matches!(l.tokens.first(), Some(Token::Directive(d)) if d == "loc"));
+    first.is_some_and(|l| matches!(&l.tokens[..], [Token::Directive(d)] if d == "named"))
+}
+
+/// The relocation of an operand naming a global or the static function, keeping the sigils of the operand.
+fn local(operand: &str, globals: &[String]) -> Option<Target> {
+    let (argument, token) = match operand.strip_prefix('%') {
+        Some(token) => ("%", token),
+        None => ("", operand),
+    };
+    if token.starts_with(['$', '&', '*']) {
+        let (sigil, ident) = token.split_at(1);
+        if globals.iter().any(|g| g == ident) {
+            return Some(Target::Local { prefix: format!("{}{}", argument, sigil), ident: ident.to_string() });
+        }
+    }
+    else if token == "static" || globals.iter().any(|g| g == token) {
+        return Some(Target::Local { prefix: argument.to_string(), ident: token.to_string() });
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::script::assembly::lexer::Token;
+    use crate::script::assembly::object::{Library, Target};
+
+    const CODE: &str = ".named
+.global count
+.export run
+@run:
+    mov i 0
+.loop:
+    add $count 1
+    call helper
+    inc i
+    jl loop $i 3
+    ret
+@helper:
+    ret
+";
+
+    fn library() -> Library {
+        Library::new("lib".to_string(), "lib.masm", CODE).unwrap()
+    }
+
+    #[test]
+    fn collects_symbols() {
+        let library = library();
+        assert!(library.named);
+        assert_eq!(library.functions, ["run", "helper"]);
+        assert_eq!(library.exports, ["run"]);
+        assert_eq!(library.globals, ["count"]);
+        assert_eq!(library.files, ["lib.masm"]);
+        assert_eq!(library.lines[0].tokens, [Token::Directive("global".to_string()), Token::Operand("count".to_string())]);
+        assert_eq!(library.lines[0].line, 2);
+    }
+
+    #[test]
+    fn collects_relocations() {
+        let library = library();
+        let targets = library.relocations.iter().map(|r| match &r.target {
+            Target::Slot { prefix, slot } => format!("slot {}{}", prefix, slot),
+            Target::Local { prefix, ident } => format!("local {}{}", prefix, ident),
+            Target::Function(function) => format!("function {}", function),
+            Target::Call(call) => format!("call {}", call),
+        }).collect::<Vec<_>>();
+        assert_eq!(targets, ["local count", "function run", "local loop", "local $count", "call helper", "local loop", "function helper"]);
+    }
+
+    #[test]
+    fn counts_slots() {
+        let library = Library::new("lib".to_string(), "lib.masm", "@f:\n    mov 2 1\n    add 0 $2\n    ret\n").unwrap();
+        assert!(!library.named);
+        assert_eq!(library.slots, 3);
+        assert!(matches!(&library.relocations[3].target, Target::Slot { prefix, slot: 2 } if prefix == "$"));
+    }
+
+    #[test]
+    fn round_trips() {
+        let bytes = library().to_bytes();
+        let library = Library::from_bytes(&bytes).unwrap();
+        assert_eq!(library.name, "lib");
+        assert_eq!(library.to_bytes(), bytes);
+    }
+
+    fn error(code: &str) -> String {
+        Library::new("lib".to_string(), "lib.masm", code).err().unwrap()
+    }
+
+    #[test]
+    fn rejects_invalid_modules() {
+        assert_eq!(error("@f:\n    mov x 1\n"), "lib.masm:2: Variable \"x\" must be a slot number, module \"lib\" does not use '.named'");
+        assert_eq!(error(".export g\n@f:\n    ret\n"), "Module \"lib\" exports unknown function \"g\"!");
+        assert_eq!(error("@f:\n    ret\n@f:\n    ret\n"), "Duplicate function \"f\" in module \"lib\"!");
+    }
+
+    #[test]
+    fn rejects_broken_files() {
+        let bytes = library().to_bytes();
+        assert!(Library::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(Library::from_bytes(b"not a library").is_err());
+    }
+}
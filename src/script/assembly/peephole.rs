@@ -0,0 +1,640 @@
+use hashbrown::{HashMap, HashSet};
+
+#[derive(Clone, Debug, PartialEq)]
+enum Line {
+    Directive(String),
+    Function(String),
+    Label(String),
+    Instruction(String, Vec<String>),
+    Other(String),
+}
+
+impl Line {
+    fn to_code(&self) -> String {
+        match self {
+            Line::Directive(s) | Line::Other(s) => s.clone(),
+            Line::Function(f) => format!("@{}:", f),
+            Line::Label(l) => format!(".{}:", l),
+            Line::Instruction(op, operands) => {
+                if operands.is_empty() {
+                    op.clone()
+                }
+                else {
+                    format!("{} {}", op, operands.join(" "))
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Value,
+    Number,
+    Primitive,
+    Str,
+}
+
+const DEST_WRITE: [&str; 7] = ["mov", "cpy", "pop", "pop_ret", "read_line", "read_all", "format"];
+const DEST_READ_WRITE: [&str; 15] = ["inc", "dec", "add", "sub", "mul", "div", "mod", "and", "or", "not", "neg", "xor", "shl", "shr", "sar"];
+const JUMPS: [&str; 7] = ["jmp", "je", "jne", "jg", "jge", "jl", "jle"];
+const VALUE_JUMPS: [&str; 4] = ["jz", "jnz", "jn", "jnn"];
+
+fn is_temp(name: &str) -> bool {
+    name.strip_prefix("_tmp").map(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit())).unwrap_or(false)
+}
+
+/// Runs a peephole pass over a single generated MASM file. This has to happen before linking, since
+/// labels are still local to the file at this point.
+pub fn peephole(code: &str) -> String {
+    let mut lines = code.lines().filter_map(parse_line).collect::<Vec<_>>();
+
+    for line in &lines {
+        if let Line::Instruction(op, operands) = line
+            && let Some(target) = jump_target(op, operands) {
+            let first = target.chars().next().unwrap_or('0');
+            if first == '+' || first == '-' || first.is_ascii_digit() {
+                return code.to_string();
+            }
+        }
+    }
+
+    loop {
+        let mut changed = false;
+        changed |= remove_self_copies(&mut lines);
+        changed |= thread_jumps(&mut lines);
+        changed |= remove_unreachable(&mut lines);
+        changed |= remove_jumps_to_next(&mut lines);
+        changed |= propagate_copies(&mut lines);
+        changed |= coalesce_temps(&mut lines);
+        changed |= remove_dead_stores(&mut lines);
+        if !changed {
+            break;
+        }
+    }
+
+    let mut result = String::new();
+    for line in lines {
+        result.push_str(&line.to_code());
+        result.push('\n');
+    }
+    result
+}
+
+fn parse_line(line: &str) -> Option<Line> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with(';') {
+        return None;
+    }
+    let tokens = split_operands(line);
+    let first = tokens[0].as_str();
+    if let Some(function) = first.strip_prefix('@') {
+        return Some(Line::Function(function.trim_end_matches(':').to_string()));
+    }
+    if let Some(label) = first.strip_prefix('.') {
+        if tokens.len() > 1 || !first.ends_with(':') && (first == ".named" || first == ".global" || first == ".use" || first == ".extern") {
+            return Some(Line::Directive(line.to_string()));
+        }
+        return Some(Line::Label(label.trim_end_matches(':').to_string()));
+    }
+    if !first.chars().all(|c| c.is_ascii_alphabetic() || c == '_') {
+        return Some(Line::Other(line.to_string()));
+    }
+    Some(Line::Instruction(first.to_ascii_lowercase(), tokens[1..].to_vec()))
+}
+
+pub(crate) fn split_operands(line: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut buffer = String::new();
+    let mut quote = None;
+    let mut escaped = false;
+    for c in line.chars() {
+        if let Some(q) = quote {
+            buffer.push(c);
+            if escaped {
+                escaped = false;
+            }
+            else if c == '\\' {
+                escaped = true;
+            }
+            else if c == q {
+                quote = None;
+            }
+        }
+        else if c.is_whitespace() {
+            if !buffer.is_empty() {
+                tokens.push(std::mem::take(&mut buffer));
+            }
+        }
+        else {
+            if c == '"' || c == '\'' {
+                quote = Some(c);
+            }
+            buffer.push(c);
+        }
+    }
+    if !buffer.is_empty() {
+        tokens.push(buffer);
+    }
+    tokens
+}
+
+fn jump_target<'a>(op: &str, operands: &'a [String]) -> Option<&'a String> {
+    if JUMPS.contains(&op) {
+        operands.first()
+    }
+    else if VALUE_JUMPS.contains(&op) {
+        operands.get(1)
+    }
+    else {
+        None
+    }
+}
+
+fn is_label_target(target: &str) -> bool {
+    !target.starts_with('$')
+}
+
+fn ends_block(op: &str) -> bool {
+    op == "jmp" || op == "ret" || op == "end"
+}
+
+fn value_name(operand: &str) -> Option<&str> {
+    let operand = operand.strip_prefix('%').unwrap_or(operand);
+    operand.strip_prefix(['$', '&', '*'])
+}
+
+fn dest_name(operand: &str) -> (&str, bool) {
+    let operand = operand.strip_prefix('$').unwrap_or(operand);
+    if let Some(name) = operand.strip_prefix('*') {
+        (name, true)
+    }
+    else {
+        (operand, false)
+    }
+}
+
+fn has_dest(op: &str) -> bool {
+    DEST_WRITE.contains(&op) || DEST_READ_WRITE.contains(&op)
+}
+
+fn value_kind(op: &str, index: usize) -> Option<Kind> {
+    match (op, index) {
+        ("mov" | "cpy", 1) => Some(Kind::Value),
+        ("add" | "sub" | "mul" | "div" | "mod" | "shl" | "shr" | "sar", 1) => Some(Kind::Number),
+        ("and" | "or" | "xor", 1) => Some(Kind::Primitive),
+        ("cmp", 0 | 1) => Some(Kind::Value),
+        ("push" | "push_ret", 0) => Some(Kind::Value),
+        ("print" | "sh" | "print_inline" | "eprint", 0) => Some(Kind::Str),
+        ("jz" | "jnz" | "jn" | "jnn", 0) => Some(Kind::Value),
+        _ => None
+    }
+}
+
+fn accepts(kind: Kind, value: &str) -> bool {
+    match kind {
+        Kind::Value => true,
+        Kind::Number => !value.starts_with('#') && value != "true" && value != "false",
+        Kind::Primitive => !value.starts_with('#'),
+        Kind::Str => value.starts_with(['#', '$', '&', '*', '%']) || value == "null",
+    }
+}
+
+fn reads(op: &str, operands: &[String], name: &str) -> bool {
+    operands.iter().enumerate().any(|(i, operand)| {
+        if i == 0 && has_dest(op) {
+            let (dest, deref) = dest_name(operand);
+            dest == name && (deref || DEST_READ_WRITE.contains(&op))
+        }
+        else if value_kind(op, i).is_some() || (op == "jmp" && i == 0) {
+            value_name(operand) == Some(name)
+        }
+        else {
+            false
+        }
+    })
+}
+
+fn writes(op: &str, operands: &[String], name: &str) -> bool {
+    has_dest(op) && operands.first().map(|o| dest_name(o) == (name, false)).unwrap_or(false)
+}
+
+fn references(op: &str, operands: &[String], name: &str) -> bool {
+    reads(op, operands, name) || writes(op, operands, name)
+}
+
+fn labels(lines: &[Line]) -> HashMap<String, usize> {
+    lines.iter().enumerate().filter_map(|(i, l)| {
+        if let Line::Label(name) = l {
+            Some((name.clone(), i))
+        }
+        else {
+            None
+        }
+    }).collect()
+}
+
+fn is_live(lines: &[Line], labels: &HashMap<String, usize>, start: usize, name: &str, visited: &mut HashSet<usize>) -> bool {
+    let mut i = start;
+    while i < lines.len() {
+        if !visited.insert(i) {
+            return false;
+        }
+        match &lines[i] {
+            Line::Function(_) => return false,
+            Line::Other(_) => return true,
+            Line::Directive(_) | Line::Label(_) => {}
+            Line::Instruction(op, operands) => {
+                if reads(op, operands, name) || operands.iter().any(|o| o.trim_start_matches('%') == format!("&{}", name)) {
+                    return true;
+                }
+                if writes(op, operands, name) {
+                    return false;
+                }
+                if op == "ret" || op == "end" {
+                    return false;
+                }
+                if let Some(target) = jump_target(op, operands) {
+                    if !is_label_target(target) {
+                        return true;
+                    }
+                    let Some(&to) = labels.get(target) else { return true; };
+                    if op == "jmp" {
+                        i = to;
+                        continue;
+                    }
+                    if is_live(lines, labels, to, name, visited) {
+                        return true;
+                    }
+                }
+            }
+        }
+        i += 1;
+    }
+    false
+}
+
+fn live_after(lines: &[Line], index: usize, name: &str) -> bool {
+    let labels = labels(lines);
+    is_live(lines, &labels, index + 1, name, &mut HashSet::new())
+}
+
+fn remove_self_copies(lines: &mut Vec<Line>) -> bool {
+    let len = lines.len();
+    lines.retain(|l| {
+        if let Line::Instruction(op, operands) = l
+            && op == "cpy" && operands.len() == 2 {
+            let (dest, deref) = dest_name(&operands[0]);
+            return deref || operands[1] != format!("${}", dest);
+        }
+        true
+    });
+    len != lines.len()
+}
+
+fn thread_jumps(lines: &mut [Line]) -> bool {
+    let labels = labels(lines);
+    let mut changed = false;
+    for i in 0..lines.len() {
+        let Line::Instruction(op, operands) = &lines[i] else { continue; };
+        let Some(target) = jump_target(op, operands) else { continue; };
+        if !is_label_target(target) {
+            continue;
+        }
+        let mut target = target.clone();
+        let mut visited = HashSet::new();
+        while let Some(&at) = labels.get(&target) {
+            if !visited.insert(at) {
+                break;
+            }
+            let next = lines[at..].iter().find(|l| !matches!(l, Line::Label(_)));
+            if let Some(Line::Instruction(next_op, next_operands)) = next
+                && let Some(next) = next_operands.first().filter(|_| next_op == "jmp")
+                && is_label_target(next) && *next != target {
+                target = next.clone();
+                continue;
+            }
+            break;
+        }
+        if let Line::Instruction(op, operands) = &mut lines[i] {
+            let index = if op == "jmp" || JUMPS.contains(&op.as_str()) { 0 } else { 1 };
+            if operands[index] != target {
+                operands[index] = target;
+                changed = true;
+            }
+        }
+    }
+    changed
+}
+
+fn remove_unreachable(lines: &mut Vec<Line>) -> bool {
+    let mut changed = false;
+    let mut dead = false;
+    let mut result = Vec::with_capacity(lines.len());
+    for line in lines.drain(..) {
+        match &line {
+            Line::Instruction(op, operands) => {
+                if dead {
+                    changed = true;
+                    continue;
+                }
+                if ends_block(op) && (op != "jmp" || operands.first().map(|t| is_label_target(t)).unwrap_or(false)) {
+                    dead = true;
+                }
+            }
+            Line::Other(_) => {
+                if dead {
+                    changed = true;
+                    continue;
+                }
+            }
+            _ => dead = false
+        }
+        result.push(line);
+    }
+    *lines = result;
+    changed
+}
+
+fn remove_jumps_to_next(lines: &mut Vec<Line>) -> bool {
+    let mut changed = false;
+    let mut i = 0;
+    while i < lines.len() {
+        if let Line::Instruction(op, operands) = &lines[i]
+            && let Some(target) = jump_target(op, operands) {
+            let mut j = i + 1;
+            let mut next = false;
+            while let Some(Line::Label(label)) = lines.get(j) {
+                if label == target {
+                    next = true;
+                    break;
+                }
+                j += 1;
+            }
+            if next {
+                lines.remove(i);
+                changed = true;
+                continue;
+            }
+            if let (Some(inverse), Some(Line::Instruction(next_op, next_operands)), Some(Line::Label(label))) = (invert(op), lines.get(i + 1), lines.get(i + 2))
+                && next_op == "jmp" && label == target && next_operands.first().map(|t| is_label_target(t)).unwrap_or(false) {
+                let to = next_operands[0].clone();
+                let mut operands = operands.clone();
+                let index = operands.len() - 1;
+                operands[index] = to;
+                lines[i] = Line::Instruction(inverse.to_string(), operands);
+                lines.remove(i + 1);
+                changed = true;
+                continue;
+            }
+        }
+        i += 1;
+    }
+    changed
+}
+
+fn invert(op: &str) -> Option<&'static str> {
+    match op {
+        "je" => Some("jne"),
+        "jne" => Some("je"),
+        "jz" => Some("jnz"),
+        "jnz" => Some("jz"),
+        "jn" => Some("jnn"),
+        "jnn" => Some("jn"),
+        _ => None
+    }
+}
+
+/// Replaces reads of a temporary with the value it was copied from, dropping the copy.
+fn propagate_copies(lines: &mut Vec<Line>) -> bool {
+    let mut i = 0;
+    let mut changed = false;
+    while i < lines.len() {
+        let Line::Instruction(op, operands) = &lines[i] else { i += 1; continue; };
+        if op != "cpy" || operands.len() != 2 {
+            i += 1;
+            continue;
+        }
+        let (temp, deref) = dest_name(&operands[0]);
+        if deref || !is_temp(temp) || value_name(&operands[1]) == Some(temp) {
+            i += 1;
+            continue;
+        }
+        let temp = temp.to_string();
+        let value = operands[1].clone();
+        let source = value_name(&value).map(|s| s.to_string());
+
+        let mut j = i + 1;
+        let mut target = None;
+        while let Some(Line::Instruction(op, operands)) = lines.get(j) {
+            if references(op, operands, &temp) {
+                target = Some(j);
+                break;
+            }
+            if op == "call" || ends_block(op) || jump_target(op, operands).is_some() {
+                break;
+            }
+            if let Some(source) = &source
+                && has_dest(op) && operands.first().map(|o| dest_name(o).0 == source).unwrap_or(false) {
+                break;
+            }
+            j += 1;
+        }
+
+        if let Some(j) = target
+            && let Line::Instruction(op, operands) = &lines[j] {
+            let dest_ok = !has_dest(op) || dest_name(&operands[0]).0 != temp;
+            let substitutable = operands.iter().enumerate().all(|(k, o)| {
+                value_name(o) != Some(temp.as_str()) || value_kind(op, k).map(|kind| {
+                    o.starts_with('$') && accepts(kind, &value)
+                }).unwrap_or(false)
+            });
+            if dest_ok && substitutable && !live_after(lines, j, &temp) {
+                if let Line::Instruction(_, operands) = &mut lines[j] {
+                    for o in operands.iter_mut() {
+                        if *o == format!("${}", temp) {
+                            *o = value.clone();
+                        }
+                    }
+                }
+                lines.remove(i);
+                changed = true;
+                continue;
+            }
+        }
+        i += 1;
+    }
+    changed
+}
+
+/// Computes directly into the destination of `cpy x $temp` instead of going through the temporary.
+/// The instruction that first writes the temporary may still read the destination, which turns
+/// `cpy _tmp0 $i; add _tmp0 1; cpy i $_tmp0` into `cpy i $i; add i 1`.
+fn coalesce_temps(lines: &mut Vec<Line>) -> bool {
+    let mut j = 0;
+    let mut changed = false;
+    while j < lines.len() {
+        let Line::Instruction(op, operands) = &lines[j] else { j += 1; continue; };
+        if op != "cpy" || operands.len() != 2 {
+            j += 1;
+            continue;
+        }
+        let (dest, deref) = dest_name(&operands[0]);
+        let Some(temp) = operands[1].strip_prefix('$') else { j += 1; continue; };
+        if deref || !is_temp(temp) || dest == temp {
+            j += 1;
+            continue;
+        }
+        let dest = dest.to_string();
+        let temp = temp.to_string();
+
+        let mut start = None;
+        let mut k = j;
+        while k > 0 {
+            k -= 1;
+            let Line::Instruction(op, operands) = &lines[k] else { break; };
+            if writes(op, operands, &temp) && !reads(op, operands, &temp) {
+                start = Some(k);
+                break;
+            }
+            if op == "call" || ends_block(op) || jump_target(op, operands).is_some() || references(op, operands, &dest) {
+                break;
+            }
+        }
+
+        if let Some(start) = start
+            && !live_after(lines, j, &temp) {
+            for line in &mut lines[start..j] {
+                if let Line::Instruction(_, operands) = line {
+                    for o in operands.iter_mut() {
+                        if *o == temp {
+                            *o = dest.clone();
+                        }
+                        else if value_name(o) == Some(temp.as_str()) {
+                            *o = o.replacen(&temp, &dest, 1);
+                        }
+                    }
+                }
+            }
+            lines.remove(j);
+            changed = true;
+            continue;
+        }
+        j += 1;
+    }
+    changed
+}
+
+fn remove_dead_stores(lines: &mut Vec<Line>) -> bool {
+    let mut i = 0;
+    let mut changed = false;
+    while i < lines.len() {
+        if let Line::Instruction(op, operands) = &lines[i]
+            && op == "cpy" && operands.len() == 2 {
+            let (temp, deref) = dest_name(&operands[0]);
+            if !deref && is_temp(temp) && !live_after(lines, i, temp) {
+                lines.remove(i);
+                changed = true;
+                continue;
+            }
+        }
+        i += 1;
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::script::assembly::peephole::{parse_line, peephole, Line};
+
+    fn instructions(code: &str) -> Vec<String> {
+        code.lines().filter_map(parse_line).filter(|l| matches!(l, Line::Instruction(..))).map(|l| l.to_code()).collect()
+    }
+
+    fn optimize(code: &str, before: usize, after: usize) -> Vec<String> {
+        assert_eq!(instructions(code).len(), before);
+        let result = instructions(&peephole(code));
+        assert_eq!(result.len(), after, "{:?}", result);
+        result
+    }
+
+    #[test]
+    fn removes_jumps_to_next_line() {
+        let result = optimize("@main:\ncmp $a 1\nje L0\n.L0:\njmp L1\n.L1:\nprint $a\nret\n", 5, 3);
+        assert_eq!(result, ["cmp $a 1", "print $a", "ret"]);
+    }
+
+    #[test]
+    fn inverts_branch_over_jump() {
+        let result = optimize("@main:\ncmp $a 1\nje L0\njmp L1\n.L0:\nprint $a\n.L1:\nret\n", 5, 4);
+        assert_eq!(result, ["cmp $a 1", "jne L1", "print $a", "ret"]);
+    }
+
+    #[test]
+    fn threads_jump_chains() {
+        let result = optimize("@main:\njz $a L0\nprint $a\n.L0:\njmp L1\n.L1:\njmp L2\n.L2:\nret\n", 5, 3);
+        assert_eq!(result, ["jz $a L2", "print $a", "ret"]);
+    }
+
+    #[test]
+    fn removes_self_copies() {
+        let result = optimize("@main:\ncpy a $a\ncpy *p $p\nprint $a\nret\n", 4, 3);
+        assert_eq!(result, ["cpy *p $p", "print $a", "ret"]);
+    }
+
+    #[test]
+    fn removes_unreachable_code() {
+        let result = optimize("@main:\nret\nprint $a\n.L0:\nprint $b\nret\n", 4, 3);
+        assert_eq!(result, ["ret", "print $b", "ret"]);
+    }
+
+    #[test]
+    fn propagates_temp_round_trips() {
+        let result = optimize("@main:\ncpy _tmp0 $a\npush $_tmp0\ncpy _tmp1 1\nadd b $_tmp1\nret\n", 5, 3);
+        assert_eq!(result, ["push $a", "add b 1", "ret"]);
+    }
+
+    #[test]
+    fn keeps_temps_read_twice() {
+        optimize("@main:\ncpy _tmp0 $a\npush $_tmp0\npush $_tmp0\nret\n", 4, 4);
+    }
+
+    #[test]
+    fn keeps_literals_out_of_string_operands() {
+        optimize("@main:\ncpy _tmp0 1\nprint $_tmp0\nret\n", 3, 3);
+    }
+
+    #[test]
+    fn coalesces_temps_into_their_destination() {
+        let result = optimize("@main:\npop_ret _tmp0\nmul _tmp0 2\ncpy x $_tmp0\nprint $x\nret\n", 5, 4);
+        assert_eq!(result, ["pop_ret x", "mul x 2", "print $x", "ret"]);
+    }
+
+    #[test]
+    fn coalesces_temps_that_read_their_destination() {
+        let result = optimize("@main:\ncpy _tmp0 $i\nadd _tmp0 1\ncpy i $_tmp0\nprint $i\nret\n", 5, 3);
+        assert_eq!(result, ["add i 1", "print $i", "ret"]);
+    }
+
+    #[test]
+    fn keeps_temps_when_destination_is_read_in_between() {
+        optimize("@main:\ncpy _tmp0 $i\nadd _tmp0 $i\nadd _tmp0 1\ncpy i $_tmp0\nprint $i\nret\n", 6, 6);
+    }
+
+    #[test]
+    fn removes_dead_stores() {
+        let result = optimize("@main:\ncpy _tmp0 1\ncpy _tmp1 $a\nprint $a\nret\n", 4, 2);
+        assert_eq!(result, ["print $a", "ret"]);
+    }
+
+    #[test]
+    fn keeps_stores_read_after_a_jump() {
+        optimize("@main:\ncpy _tmp0 $a\njz $b L0\nret\n.L0:\npush $_tmp0\nret\n", 5, 5);
+    }
+
+    #[test]
+    fn leaves_relative_jumps_alone() {
+        let code = "@main:\njmp +1\ncpy a $a\nret\n";
+        assert_eq!(peephole(code), code);
+    }
+}
@@ -0,0 +1,83 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::time::UNIX_EPOCH;
+use crate::script::compiler::generate;
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Compiles MVScript source to MASM, reusing the output of a previous compilation of the same source
+/// by the same compiler build if there is one. Sources with warnings are not cached, so the warnings
+/// are shown on every build.
+pub fn compile(code: String, optimize: bool) -> String {
+    let Some(compiler) = compiler() else {
+        return generate(code, optimize).0;
+    };
+    let path = dir().join(format!("{:016x}.masm", key(&compiler, &code, optimize)));
+    if let Ok(masm) = fs::read_to_string(&path) {
+        return masm;
+    }
+    let (masm, warnings) = generate(code, optimize);
+    if warnings.is_empty() {
+        store(&path, &masm);
+    }
+    masm
+}
+
+/// The cache directory, `$MVC_CACHE` or `mvc` inside the user cache directory.
+pub fn dir() -> PathBuf {
+    if let Some(dir) = env::var_os("MVC_CACHE") {
+        return PathBuf::from(dir);
+    }
+    if let Some(dir) = env::var_os("XDG_CACHE_HOME") {
+        return PathBuf::from(dir).join("mvc");
+    }
+    if let Some(home) = env::var_os("HOME").or_else(|| env::var_os("USERPROFILE")) {
+        return PathBuf::from(home).join(".cache").join("mvc");
+    }
+    env::temp_dir().join("mvc-cache")
+}
+
+pub fn clean() -> std::io::Result<()> {
+    let dir = dir();
+    if dir.exists() {
+        fs::remove_dir_all(dir)?;
+    }
+    Ok(())
+}
+
+/// Identifies the running compiler by its version and the size and modification time of its binary,
+/// which change with every build even if the version does not. The cache is not used without it.
+fn compiler() -> Option<String> {
+    let metadata = env::current_exe().and_then(fs::metadata).ok()?;
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some(format!("{} {} {}", VERSION, metadata.len(), modified.as_nanos()))
+}
+
+/// 64 bit FNV-1a over the compiler, the optimisation flag and the source, which stays stable across
+/// runs unlike the std hasher.
+fn key(compiler: &str, code: &str, optimize: bool) -> u64 {
+    let mut hash = 0xcbf29ce484222325u64;
+    for part in [compiler.as_bytes(), &[0, optimize as u8, 0], code.as_bytes()] {
+        for b in part {
+            hash ^= *b as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    hash
+}
+
+/// Writes through a temporary file so concurrent builds never read a partially written entry.
+/// Failing to write the cache only costs a recompilation, so errors are ignored.
+fn store(path: &PathBuf, masm: &str) {
+    let Some(dir) = path.parent() else {
+        return;
+    };
+    if fs::create_dir_all(dir).is_err() {
+        return;
+    }
+    let tmp = path.with_extension(format!("tmp{}", std::process::id()));
+    if fs::write(&tmp, masm).is_ok() && fs::rename(&tmp, path).is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+}
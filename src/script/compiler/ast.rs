@@ -4,12 +4,14 @@ use crate::script::compiler::parser::*;
 #[derive(Debug, Clone)]
 pub struct Program {
     pub elements: Vec<Element>,
+    pub warnings: Vec<ParseError>,
 }
 
 impl Program {
     pub fn new() -> Self {
         Self {
             elements: Vec::new(),
+            warnings: Vec::new(),
         }
     }
 
@@ -43,6 +45,7 @@ pub enum Statement {
     If(IfStatement),
     While(WhileStatement),
     For(ForStatement),
+    Match(MatchStatement),
     Return(Option<Expression>),
     Noop,
 }
@@ -71,6 +74,9 @@ impl Expression {
                 }
             }
             Expression::Binary(binary) => {
+                if binary.operator.is_boolean() {
+                    return Some(Type::Bool);
+                }
                 let a = binary.left.infer_type();
                 let b = binary.right.infer_type();
                 if a.is_none() {
@@ -103,6 +109,19 @@ impl Expression {
                 unary.expr.infer_type()
             }
             Expression::Argument(_) => Some(Type::String),
+            Expression::Call(c) => match c.function.as_str() {
+                "abs" | "floor" | "ceil" | "round" => c.arguments.first()?.infer_type(),
+                "min" | "max" | "clamp" | "pow" => {
+                    let types = c.arguments.iter().map(|a| a.infer_type()).collect::<Option<Vec<_>>>()?;
+                    if types.contains(&Type::Float) {
+                        Some(Type::Float)
+                    }
+                    else {
+                        Some(Type::Int)
+                    }
+                }
+                function => builtin_type(function)
+            },
             _ => None
         }
     }
@@ -129,6 +148,28 @@ impl Expression {
     }
 }
 
+/// The return type of the builtin function `name`, if it is a builtin that returns a value.
+pub fn builtin_type(name: &str) -> Option<Type> {
+    match name {
+        "sh" | "sh_in" | "proc" | "proc_run" | "proc_wait" | "proc_status" | "file_size" | "mtime" | "list_len"
+        | "len" | "index_of" => Some(Type::Int),
+        "shout" | "shout_err" | "shout_in" | "proc_output" | "proc_error" | "read_file" | "last_error"
+        | "substring" | "join" | "trim" | "replace" | "upper" | "lower" | "repeat" | "format_number" => Some(Type::String),
+        "write_file" | "append_file" | "exists" | "is_dir" | "mkdir" | "remove" | "copy" | "rename"
+        | "starts_with" | "ends_with" | "chdir" | "map_has"
+        | "git_add" | "git_add_all" | "git_commit" | "git_commit_default" | "git_push" | "git_push_upstream"
+        | "git_tag" | "git_checkout" | "git_fetch" | "git_pull" => Some(Type::Bool),
+        "char_at" | "char" => Some(Type::Char),
+        "int" | "gcd" | "arg_count" | "pid" => Some(Type::Int),
+        "float" | "sqrt" | "sin" | "cos" | "tan" | "asin" | "acos" | "atan" | "atan2" | "log" | "log10" | "exp" => Some(Type::Float),
+        "bool" => Some(Type::Bool),
+        "string" | "typeof" | "env" | "cwd" | "git_branch" | "read_line" | "read_all" | "format" | "json_stringify" => Some(Type::String),
+        "list_dir" | "list_new" | "list_push" | "split" | "git_status" | "git_log" | "git_diff_stats" | "map_keys" => Some(Type::List),
+        "map_new" | "map_set" => Some(Type::Map),
+        _ => None
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Type {
     Int,
@@ -136,9 +177,41 @@ pub enum Type {
     Char,
     String,
     Bool,
+    List,
+    Map,
     Void
 }
 
+impl Type {
+    /// Whether a value of this type can be converted to `ty` by its conversion builtin, although the
+    /// conversion of a string may still fail at runtime.
+    pub fn converts_to(&self, ty: &Type) -> bool {
+        match ty {
+            Type::String => *self != Type::Void,
+            Type::Int => matches!(self, Type::Int | Type::Float | Type::Char | Type::Bool | Type::String),
+            Type::Float => matches!(self, Type::Int | Type::Float | Type::String),
+            Type::Char => matches!(self, Type::Int | Type::Char | Type::String),
+            Type::Bool => matches!(self, Type::Bool | Type::Int | Type::String),
+            _ => self == ty
+        }
+    }
+}
+
+impl std::fmt::Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Type::Int => "int",
+            Type::Float => "float",
+            Type::Char => "char",
+            Type::String => "String",
+            Type::Bool => "bool",
+            Type::List => "List",
+            Type::Map => "Map",
+            Type::Void => "void"
+        })
+    }
+}
+
 impl TryFrom<Keyword> for Type {
     type Error = ParseError;
 
@@ -149,6 +222,8 @@ impl TryFrom<Keyword> for Type {
             Keyword::String => Ok(Type::String),
             Keyword::Bool => Ok(Type::Bool),
             Keyword::Char => Ok(Type::Char),
+            Keyword::List => Ok(Type::List),
+            Keyword::Map => Ok(Type::Map),
             _ => Err(format!("Type: Invalid keyword for type {}", k).into())
         }
     }
@@ -193,9 +268,23 @@ pub struct ForStatement {
     pub body: Box<Statement>
 }
 
+#[derive(Debug, Clone)]
+pub struct MatchStatement {
+    pub value: Expression,
+    pub arms: Vec<MatchArm>,
+    pub default: Option<Box<Statement>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MatchArm {
+    pub patterns: Vec<Literal>,
+    pub body: Box<Statement>,
+}
+
 #[derive(Debug, Clone)]
 pub struct Function {
     pub name: String,
+    pub public: bool,
     pub parameters: Vec<(String, Type)>,
     pub return_type: Type,
     pub body: Block,
@@ -1,4 +1,5 @@
-use crate::script::compiler::ast::{Element, Expression, ForStatement, Function, IfStatement, Program, Statement, TopLevelStatement, WhileStatement};
+use crate::script::assembly::lexer as masm;
+use crate::script::compiler::ast::{Element, Expression, ForStatement, Function, IfStatement, MatchStatement, Program, Statement, TopLevelStatement, WhileStatement};
 use crate::script::compiler::lexer::{Literal, Operator};
 
 pub struct Generator {
@@ -17,7 +18,9 @@ impl Generator {
             preload_code: String::new(),
             lib: true,
             next_label: String::new(),
-            label_stack: Vec::new()
+            label_stack: Vec::new(),
+            next_temp: 0,
+            free_temps: Vec::new()
         };
 
         for f in &self.program.elements {
@@ -49,6 +52,8 @@ pub struct StaticData {
     pub lib: bool,
     pub next_label: String,
     pub label_stack: Vec<String>,
+    pub next_temp: u32,
+    pub free_temps: Vec<String>,
 }
 
 impl StaticData {
@@ -62,6 +67,20 @@ impl StaticData {
         }
         self.next_label.clone()
     }
+
+    pub fn alloc_temp(&mut self) -> String {
+        if let Some(temp) = self.free_temps.pop() {
+            temp
+        }
+        else {
+            self.next_temp += 1;
+            format!("_tmp{}", self.next_temp - 1)
+        }
+    }
+
+    pub fn free_temp(&mut self, temp: String) {
+        self.free_temps.push(temp);
+    }
 }
 
 pub trait Codegen: Sized {
@@ -98,9 +117,12 @@ impl Codegen for TopLevelStatement {
                 let mut code = String::new();
                 code.push_str(&format!(".global {}\n", d.name));
                 if let Some(v) = d.value {
-                    let load = v.codegen(data);
+                    let (load, value, temp) = v.codegen_operand(data);
                     data.preload_code.push_str(&load);
-                    data.preload_code.push_str(&format!("cpy {} $_tmp\n", d.name));
+                    data.preload_code.push_str(&format!("cpy {} {}\n", d.name, value));
+                    if let Some(temp) = temp {
+                        data.free_temp(temp);
+                    }
                 }
                 code
             },
@@ -119,6 +141,9 @@ impl Codegen for TopLevelStatement {
 impl Codegen for Function {
     fn codegen(self, data: &mut StaticData) -> String {
         let mut code = String::new();
+        if self.public {
+            code.push_str(&format!(".export {}\n", self.name));
+        }
         code.push_str(&format!("@{}:\n", self.name));
         if self.name == "main" || data.lib {
             code.push_str(&format!("call {}\n", data.preload_name));
@@ -150,8 +175,12 @@ impl Codegen for Statement {
             Statement::Declaration(d) => {
                 let mut code = String::new();
                 if let Some(v) = d.value {
-                    code.push_str(&v.codegen(data));
-                    code.push_str(&format!("cpy {} $_tmp\n", d.name));
+                    let (load, value, temp) = v.codegen_operand(data);
+                    code.push_str(&load);
+                    code.push_str(&format!("cpy {} {}\n", d.name, value));
+                    if let Some(temp) = temp {
+                        data.free_temp(temp);
+                    }
                 }
                 else {
                     code.push_str(&format!("cpy {} null\n", d.name));
@@ -160,8 +189,12 @@ impl Codegen for Statement {
             },
             Statement::Assignment(a) => {
                 let mut code = String::new();
-                code.push_str(&a.value.codegen(data));
-                code.push_str(format!("cpy {} $_tmp\n", a.name).as_str());
+                let (load, value, temp) = a.value.codegen_operand(data);
+                code.push_str(&load);
+                code.push_str(format!("cpy {} {}\n", a.name, value).as_str());
+                if let Some(temp) = temp {
+                    data.free_temp(temp);
+                }
                 code
             },
             Statement::Break => {
@@ -176,11 +209,16 @@ impl Codegen for Statement {
             Statement::If(i) => i.codegen(data),
             Statement::While(w) => w.codegen(data),
             Statement::For(f) => f.codegen(data),
+            Statement::Match(m) => m.codegen(data),
             Statement::Return(r) => {
                 let mut code = String::new();
                 if let Some(v) = r {
-                    code.push_str(&v.codegen(data));
-                    code.push_str("push_ret $_tmp\n");
+                    let (load, value, temp) = v.codegen_operand(data);
+                    code.push_str(&load);
+                    code.push_str(&format!("push_ret {}\n", value));
+                    if let Some(temp) = temp {
+                        data.free_temp(temp);
+                    }
                 }
                 code.push_str("ret\n");
                 code
@@ -230,77 +268,42 @@ impl Codegen for ForStatement {
     }
 }
 
-impl Codegen for Expression {
+impl Codegen for MatchStatement {
     fn codegen(self, data: &mut StaticData) -> String {
-        match self {
-            Expression::Literal(l) => {
-                format!("cpy _tmp {}\n", match l {
-                    Literal::Integer(i) => i.to_string(),
-                    Literal::Float(f) => f.to_string(),
-                    Literal::Char(c) => format!("'{}'", c),
-                    Literal::String(s) => format!("#\"{}\"", s),
-                    Literal::Bool(b) => b.to_string(),
-                    Literal::Null => "null".to_string()
-                })
-            }
-            Expression::Identifier(i) => format!("cpy _tmp ${}\n", i),
-            Expression::Binary(b) => {
-                let mut code = String::new();
-                code.push_str(&b.right.codegen(data));
-                code.push_str("cpy _tmp2 $_tmp\n");
-                code.push_str(&b.left.codegen(data));
-                code.push_str(&format!("{} _tmp $_tmp2\n", match b.operator {
-                    Operator::Plus => "add",
-                    Operator::Minus => "sub",
-                    Operator::Multiply => "mul",
-                    Operator::Divide => "div",
-                    Operator::Modulo => "mod",
-                    Operator::BitwiseAnd => "and",
-                    Operator::BitwiseOr => "or",
-                    Operator::Xor => "xor",
-                    Operator::LeftShift => "shl",
-                    Operator::LogicalRightShift => "shr",
-                    Operator::ArithmeticRightShift => "sar",
-                    _ => panic!("Operator {} not supported or not implemented for non-conditional codegen!", b.operator)
-                }));
-                code
-            }
-            Expression::Unary(u) => {
-                let mut code = String::new();
-                code.push_str(&u.expr.codegen(data));
-                code.push_str(match u.operator {
-                    Operator::Minus => "neg _tmp\n",
-                    Operator::Not => "not _tmp\n",
-                    _ => panic!("Unary expression with non-unary operator {}!", u.operator)
-                });
-                code
-            }
-            Expression::Call(mut c) => {
-                let mut code = String::new();
-                c.arguments.reverse();
-                if c.function == "print" || c.function == "sh" {
-                    if c.arguments.len() != 1 {
-                        panic!("Illegal number of arguments for builtin {}!", c.function);
-                    }
-                    code.push_str(&c.arguments.pop().unwrap().codegen(data));
-                    code.push_str(&format!("{} $_tmp\n", c.function));                }
-                else {
-                    for arg in c.arguments {
-                        code.push_str(&arg.codegen(data));
-                        code.push_str("push $_tmp\n");
-                    }
-                    code.push_str(&format!("call {}\n", c.function));
-                    code.push_str("pop_ret _tmp\n");
-                }
-                code
-            }
-            Expression::Argument(a) => {
-                let mut code = String::new();
-                code.push_str(&a.codegen(data));
-                code.push_str("cpy _tmp %$_tmp\n");
-                code
+        let after_label = data.next_label();
+        let (mut code, value, temp) = self.value.codegen_operand(data);
+        let mut arms = Vec::new();
+        for arm in self.arms {
+            let arm_label = data.next_label();
+            for pattern in arm.patterns {
+                code.push_str(&format!("cmp {} {}\nje {}\n", value, literal(pattern), arm_label));
             }
+            arms.push((arm_label, arm.body));
         }
+        if let Some(temp) = temp {
+            data.free_temp(temp);
+        }
+        let default_label = self.default.as_ref().map(|_| data.next_label());
+        code.push_str(&format!("jmp {}\n", default_label.as_ref().unwrap_or(&after_label)));
+        for (label, body) in arms {
+            let block = body.codegen(data);
+            code.push_str(&format!(".{}:\n{}jmp {}\n", label, block, after_label));
+        }
+        if let (Some(label), Some(body)) = (default_label, self.default) {
+            let block = body.codegen(data);
+            code.push_str(&format!(".{}:\n{}", label, block));
+        }
+        code.push_str(&format!(".{}:\n", after_label));
+        code
+    }
+}
+
+impl Codegen for Expression {
+    fn codegen(self, data: &mut StaticData) -> String {
+        let temp = data.alloc_temp();
+        let code = self.codegen_into(data, &temp);
+        data.free_temp(temp);
+        code
     }
 
     fn codegen_conditional(self, data: &mut StaticData, true_label: &str, false_label: &str) -> String {
@@ -309,13 +312,13 @@ impl Codegen for Expression {
                 match l {
                     Literal::Integer(i) => format!("jnz {} {}\njmp {}\n", i, true_label, false_label),
                     Literal::Float(f) => format!("jnz {} {}\njmp {}\n", f, true_label, false_label),
-                    Literal::Char(c) => format!("jnz {} {}\njmp {}\n", c, true_label, false_label),
-                    Literal::String(s) => format!("cmp {} #\"\"\njne {}\n jmp {}\n", s, true_label, false_label),
-                    Literal::Bool(b) => format!("cmp {} true\nje {}\njne {}\n", b, true_label, false_label),
+                    Literal::Char(c) => format!("jnz {} {}\njmp {}\n", literal(Literal::Char(c)), true_label, false_label),
+                    Literal::String(s) => format!("cmp #\"{}\" #\"\"\njne {}\njmp {}\n", s, true_label, false_label),
+                    Literal::Bool(b) => format!("jmp {}\n", if b { true_label } else { false_label }),
                     Literal::Null => format!("jmp {}\n", false_label),
                 }
             }
-            Expression::Identifier(i) => format!("cmp ${} true\n je {}\n, jmp {}\n", i, true_label, false_label),
+            Expression::Identifier(i) => format!("cmp ${} true\nje {}\njmp {}\n", i, true_label, false_label),
             Expression::Binary(b) => {
                 if b.operator == Operator::And {
                     let and_true_label = data.next_label();
@@ -334,19 +337,34 @@ impl Codegen for Expression {
                     if b.operator == Operator::Equal || b.operator == Operator::NotEqual {
                         let n = if b.operator == Operator::Equal { "" } else { "n" };
                         if b.left.is_null() || b.left.is_zero() {
-                            code.push_str(&b.right.codegen(data));
-                            code.push_str(&format!("j{}{} $_tmp {}\njmp {}\n", n, if b.left.is_null() { "n" } else { "z" }, true_label, false_label));
+                            let z = if b.left.is_null() { "n" } else { "z" };
+                            let (load, value, temp) = b.right.codegen_operand(data);
+                            code.push_str(&load);
+                            code.push_str(&format!("j{}{} {} {}\njmp {}\n", n, z, value, true_label, false_label));
+                            if let Some(temp) = temp {
+                                data.free_temp(temp);
+                            }
+                            return code;
                         }
                         else if b.right.is_null() || b.right.is_zero() {
-                            code.push_str(&b.left.codegen(data));
-                            code.push_str(&format!("j{}{} $_tmp {}\njmp {}\n", n, if b.right.is_null() { "n" } else { "z" }, true_label, false_label));
+                            let z = if b.right.is_null() { "n" } else { "z" };
+                            let (load, value, temp) = b.left.codegen_operand(data);
+                            code.push_str(&load);
+                            code.push_str(&format!("j{}{} {} {}\njmp {}\n", n, z, value, true_label, false_label));
+                            if let Some(temp) = temp {
+                                data.free_temp(temp);
+                            }
+                            return code;
                         }
-                        return code;
                     }
-                    code.push_str(&b.right.codegen(data));
-                    code.push_str("cpy _tmp2 $_tmp\n");
-                    code.push_str(&b.left.codegen(data));
-                    code.push_str("cmp $_tmp $_tmp2\n");
+                    let (left_load, left, left_temp) = b.left.codegen_operand(data);
+                    let (right_load, right, right_temp) = b.right.codegen_operand(data);
+                    code.push_str(&left_load);
+                    code.push_str(&right_load);
+                    code.push_str(&format!("cmp {} {}\n", left, right));
+                    for temp in [left_temp, right_temp].into_iter().flatten() {
+                        data.free_temp(temp);
+                    }
                     code.push_str(&format!("{} {}\n", match b.operator {
                         Operator::Equal => "je",
                         Operator::NotEqual => "jne",
@@ -366,26 +384,168 @@ impl Codegen for Expression {
                 }
                 u.expr.codegen_conditional(data, false_label, true_label)
             }
+            e => {
+                if let Expression::Call(c) = &e && matches!(c.function.as_str(), "print" | "sh" | "print_inline" | "eprint") {
+                    panic!("Builtin function does not return a boolean value!");
+                }
+                let temp = data.alloc_temp();
+                let mut code = e.codegen_into(data, &temp);
+                code.push_str(&format!("cmp ${} true\n", temp));
+                data.free_temp(temp);
+                code.push_str(&format!("je {}\n", true_label));
+                code.push_str(&format!("jmp {}\n", false_label));
+                code
+            }
+        }
+    }
+}
+
+impl Expression {
+    pub fn codegen_into(self, data: &mut StaticData, dest: &str) -> String {
+        match self {
+            Expression::Literal(l) => format!("cpy {} {}\n", dest, literal(l)),
+            Expression::Identifier(i) => format!("cpy {} ${}\n", dest, i),
+            Expression::Binary(b) if b.operator.is_boolean() => {
+                let true_label = data.next_label();
+                let false_label = data.next_label();
+                let after_label = data.next_label();
+                let cond = Expression::Binary(b).codegen_conditional(data, &true_label, &false_label);
+                format!("{}.{}:\ncpy {} true\njmp {}\n.{}:\ncpy {} false\n.{}:\n", cond, true_label, dest, after_label, false_label, dest, after_label)
+            }
+            Expression::Binary(b) => {
+                let op = match b.operator {
+                    Operator::Plus => "add",
+                    Operator::Minus => "sub",
+                    Operator::Multiply => "mul",
+                    Operator::Divide => "div",
+                    Operator::Modulo => "mod",
+                    Operator::BitwiseAnd => "and",
+                    Operator::BitwiseOr => "or",
+                    Operator::Xor => "xor",
+                    Operator::LeftShift => "shl",
+                    Operator::LogicalRightShift => "shr",
+                    Operator::ArithmeticRightShift => "sar",
+                    _ => panic!("Operator {} not supported or not implemented for non-conditional codegen!", b.operator)
+                };
+                let mut code = b.left.codegen_into(data, dest);
+                let primitive = op == "and" || op == "or" || op == "xor";
+                match *b.right {
+                    Expression::Identifier(i) => code.push_str(&format!("{} {} ${}\n", op, dest, i)),
+                    Expression::Literal(l) if !matches!(l, Literal::String(_)) && (primitive || !matches!(l, Literal::Bool(_))) => {
+                        code.push_str(&format!("{} {} {}\n", op, dest, literal(l)));
+                    }
+                    right => {
+                        let temp = data.alloc_temp();
+                        code.push_str(&right.codegen_into(data, &temp));
+                        code.push_str(&format!("{} {} ${}\n", op, dest, temp));
+                        data.free_temp(temp);
+                    }
+                }
+                code
+            }
+            Expression::Unary(u) => {
+                let mut code = u.expr.codegen_into(data, dest);
+                code.push_str(&match u.operator {
+                    Operator::Minus => format!("neg {}\n", dest),
+                    Operator::Not => format!("not {}\n", dest),
+                    _ => panic!("Unary expression with non-unary operator {}!", u.operator)
+                });
+                code
+            }
             Expression::Call(mut c) => {
                 let mut code = String::new();
                 c.arguments.reverse();
-                if c.function == "print" || c.function == "sh" {
-                    panic!("Builtin function does not return a boolean value!");
+                if matches!(c.function.as_str(), "print" | "sh" | "print_inline" | "eprint") {
+                    if c.arguments.len() != 1 {
+                        panic!("Illegal number of arguments for builtin {}!", c.function);
+                    }
+                    let arg = c.arguments.pop().unwrap();
+                    if let Expression::Identifier(i) = arg {
+                        code.push_str(&format!("{} ${}\n", c.function, i));
+                    }
+                    else if let Expression::Literal(Literal::String(s)) = arg {
+                        code.push_str(&format!("{} #\"{}\"\n", c.function, s));
+                    }
+                    else {
+                        code.push_str(&arg.codegen_into(data, dest));
+                        code.push_str(&format!("{} ${}\n", c.function, dest));
+                    }
+                    if c.function == "sh" {
+                        code.push_str(&format!("pop_ret {}\n", dest));
+                    }
+                }
+                else if c.function == "read_line" || c.function == "read_all" {
+                    if !c.arguments.is_empty() {
+                        panic!("Illegal number of arguments for builtin {}!", c.function);
+                    }
+                    code.push_str(&format!("{} {}\n", c.function, dest));
+                }
+                else if c.function == "format" {
+                    let Some(Expression::Literal(Literal::String(fmt))) = c.arguments.last() else {
+                        panic!("The first argument of format must be a string literal!");
+                    };
+                    let placeholders = fmt.replace("{{", "").matches("{}").count();
+                    if placeholders != c.arguments.len() - 1 {
+                        panic!("Format string \"{}\" expects {} arguments, found {}!", fmt, placeholders, c.arguments.len() - 1);
+                    }
+                    for arg in c.arguments {
+                        let (load, value, temp) = arg.codegen_operand(data);
+                        code.push_str(&load);
+                        code.push_str(&format!("push {}\n", value));
+                        if let Some(temp) = temp {
+                            data.free_temp(temp);
+                        }
+                    }
+                    code.push_str(&format!("format {}\n", dest));
                 }
                 else {
                     for arg in c.arguments {
-                        code.push_str(&arg.codegen(data));
-                        code.push_str("push $_tmp\n");
+                        let (load, value, temp) = arg.codegen_operand(data);
+                        code.push_str(&load);
+                        code.push_str(&format!("push {}\n", value));
+                        if let Some(temp) = temp {
+                            data.free_temp(temp);
+                        }
                     }
                     code.push_str(&format!("call {}\n", c.function));
-                    code.push_str("pop_ret _tmp\n");
+                    code.push_str(&format!("pop_ret {}\n", dest));
                 }
-                code.push_str("cmp $_tmp true\n");
-                code.push_str(&format!("je {}\n", true_label));
-                code.push_str(&format!("jmp {}\n", false_label));
                 code
             }
-            _ => panic!("This expression is not supported for conditional codegen!")
+            Expression::Argument(a) => {
+                if let Expression::Literal(Literal::Integer(i)) = *a {
+                    return format!("cpy {} %{}\n", dest, i);
+                }
+                let mut code = a.codegen_into(data, dest);
+                code.push_str(&format!("cpy {} %${}\n", dest, dest));
+                code
+            }
+        }
+    }
+
+    /// Generates the code needed to evaluate this expression and returns it together with an operand
+    /// referring to the result. Literals and identifiers are used directly, anything else is evaluated
+    /// into a temporary, which the caller has to free once the operand is no longer needed.
+    pub fn codegen_operand(self, data: &mut StaticData) -> (String, String, Option<String>) {
+        match self {
+            Expression::Literal(l) => (String::new(), literal(l), None),
+            Expression::Identifier(i) => (String::new(), format!("${}", i), None),
+            e => {
+                let temp = data.alloc_temp();
+                let code = e.codegen_into(data, &temp);
+                (code, format!("${}", temp), Some(temp))
+            }
         }
     }
-}
\ No newline at end of file
+}
+
+fn literal(l: Literal) -> String {
+    match l {
+        Literal::Integer(i) => i.to_string(),
+        Literal::Float(f) => f.to_string(),
+        Literal::Char(c) => masm::Token::Char(c).to_string(),
+        Literal::String(s) => format!("#\"{}\"", s),
+        Literal::Bool(b) => b.to_string(),
+        Literal::Null => "null".to_string()
+    }
+}
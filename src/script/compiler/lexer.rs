@@ -23,9 +23,11 @@ pub enum Token {
     Comma,
     Dot,
     Colon,
+    DoubleColon,
     Semicolon,
     Arrow,
     ThickArrow,
+    Underscore,
     Eof
 }
 
@@ -46,9 +48,11 @@ impl Display for Token {
             Token::Comma => ",".to_string(),
             Token::Dot => ".".to_string(),
             Token::Colon => ":".to_string(),
+            Token::DoubleColon => "::".to_string(),
             Token::Semicolon => ";".to_string(),
             Token::Arrow => "->".to_string(),
             Token::ThickArrow => "=>".to_string(),
+            Token::Underscore => "_".to_string(),
             Token::Eof => "eof".to_string()
         };
         f.write_str(&s)
@@ -63,6 +67,7 @@ pub enum Keyword {
     Let,
     Const,
     Fn,
+    Pub,
     If,
     Else,
     While,
@@ -71,11 +76,15 @@ pub enum Keyword {
     Return,
     Break,
     Continue,
+    Match,
     Int,
     Float,
     String,
     Bool,
-    Char
+    Char,
+    List,
+    Map,
+    As
 }
 
 impl Display for Keyword {
@@ -87,6 +96,7 @@ impl Display for Keyword {
             Keyword::Let => "let",
             Keyword::Const => "const",
             Keyword::Fn => "fn",
+            Keyword::Pub => "pub",
             Keyword::If => "if",
             Keyword::Else => "else",
             Keyword::While => "while",
@@ -95,11 +105,15 @@ impl Display for Keyword {
             Keyword::Return => "return",
             Keyword::Break => "break",
             Keyword::Continue => "continue",
+            Keyword::Match => "match",
             Keyword::Int => "int",
             Keyword::Float => "float",
             Keyword::String => "String",
             Keyword::Bool => "bool",
-            Keyword::Char => "char"
+            Keyword::Char => "char",
+            Keyword::List => "List",
+            Keyword::Map => "Map",
+            Keyword::As => "as"
         };
         f.write_str(s)
     }
@@ -112,6 +126,7 @@ static KEYWORDS: Map<&'static str, Keyword> = phf_map! {
     "let" => Keyword::Let,
     "const" => Keyword::Const,
     "fn" => Keyword::Fn,
+    "pub" => Keyword::Pub,
     "if" => Keyword::If,
     "else" => Keyword::Else,
     "while" => Keyword::While,
@@ -120,11 +135,15 @@ static KEYWORDS: Map<&'static str, Keyword> = phf_map! {
     "return" => Keyword::Return,
     "break" => Keyword::Break,
     "continue" => Keyword::Continue,
+    "match" => Keyword::Match,
     "int" => Keyword::Int,
     "float" => Keyword::Float,
     "String" => Keyword::String,
     "bool" => Keyword::Bool,
-    "char" => Keyword::Char
+    "char" => Keyword::Char,
+    "List" => Keyword::List,
+    "Map" => Keyword::Map,
+    "as" => Keyword::As
 };
 
 #[derive(Debug, Clone, Eq, PartialEq)]
@@ -161,6 +180,19 @@ impl Operator {
         }
     }
 
+    pub fn is_boolean(&self) -> bool {
+        matches!(self,
+            Operator::Equal |
+            Operator::NotEqual |
+            Operator::LessThan |
+            Operator::GreaterThan |
+            Operator::LessOrEqual |
+            Operator::GreaterOrEqual |
+            Operator::And |
+            Operator::Or
+        )
+    }
+
     pub fn precedence(&self) -> Result<u8, ParseError> {
         match self {
             Operator::Not => Ok(7),
@@ -325,9 +357,6 @@ impl Lexer {
                     if buffer == "static" {
                         panic!("Static is a reserved name!");
                     }
-                    else if buffer == "_tmp" || buffer == "_tmp2" {
-                        panic!("_tmp and _tmp2 are reserved names!");
-                    }
                     return if buffer == "true" {
                         Token::Literal(Literal::Bool(true))
                     }
@@ -384,8 +413,17 @@ impl Lexer {
                         '}' => Token::RCurly,
                         ',' => Token::Comma,
                         '.' => Token::Dot,
-                        ':' => Token::Colon,
+                        ':' => {
+                            match self.chars.peek() {
+                                Some(':') => {
+                                    self.chars.next();
+                                    Token::DoubleColon
+                                }
+                                _ => Token::Colon
+                            }
+                        }
                         ';' => Token::Semicolon,
+                        '_' => Token::Underscore,
                         '-' => {
                             match self.chars.peek() {
                                 Some('>') => {
@@ -448,7 +486,7 @@ impl Lexer {
                             match self.chars.peek() {
                                 Some('=') => {
                                     self.chars.next();
-                                    Token::OperatorAssign(Operator::LessOrEqual)
+                                    Token::Operator(Operator::LessOrEqual)
                                 }
                                 Some('<') => {
                                     self.chars.next();
@@ -467,14 +505,14 @@ impl Lexer {
                             match self.chars.peek() {
                                 Some('=') => {
                                     self.chars.next();
-                                    Token::OperatorAssign(Operator::GreaterOrEqual)
+                                    Token::Operator(Operator::GreaterOrEqual)
                                 }
                                 Some('>') => {
                                     self.chars.next();
                                     match self.chars.peek() {
                                         Some('=') => {
                                             self.chars.next();
-                                            Token::Operator(Operator::GreaterOrEqual)
+                                            Token::OperatorAssign(Operator::ArithmeticRightShift)
                                         }
                                         Some('>') => {
                                             self.chars.next();
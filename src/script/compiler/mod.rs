@@ -1,4 +1,46 @@
+use std::process::exit;
+use crate::script::assembly::peephole::peephole;
+use crate::script::compiler::codegen::Generator;
+use crate::script::compiler::lexer::Lexer;
+use crate::script::compiler::optimizer::Optimizer;
+use crate::script::compiler::parser::{ParseError, Parser};
+
 pub mod lexer;
 pub mod ast;
 pub mod parser;
-pub mod codegen;
\ No newline at end of file
+pub mod codegen;
+pub mod optimizer;
+
+/// Compiles MVScript source code to MASM. Warnings are printed and returned alongside the code.
+pub fn generate(code: String, optimize: bool) -> (String, Vec<ParseError>) {
+    let lexer = Lexer::new(code);
+
+    let parser = Parser::new(lexer);
+
+    let result = parser.parse();
+
+    if let Err(e) = result {
+        println!("{:?}", e);
+        exit(1);
+    }
+    let mut result = result.unwrap();
+    let warnings = std::mem::take(&mut result.warnings);
+    for warning in &warnings {
+        eprintln!("Warning: {}", warning);
+    }
+
+    if optimize {
+        result = Optimizer::new(result).optimize();
+    }
+
+    let generator = Generator::new(result);
+
+    let script = generator.generate();
+
+    if optimize {
+        (peephole(&script), warnings)
+    }
+    else {
+        (script, warnings)
+    }
+}
@@ -0,0 +1,593 @@
+use std::cmp::Ordering;
+use crate::script::compiler::ast::{Block, Element, Expression, ForStatement, Function, IfStatement, MatchArm, MatchStatement, Program, Statement, TopLevelStatement, Type, WhileStatement};
+use crate::script::compiler::lexer::{Literal, Operator};
+
+pub struct Optimizer {
+    pub program: Program,
+}
+
+impl Optimizer {
+    pub fn new(program: Program) -> Self {
+        Self { program }
+    }
+
+    pub fn optimize(self) -> Program {
+        let mut data = OptimizerData {
+            globals: Vec::new(),
+            declared: Vec::new(),
+            used: Vec::new()
+        };
+
+        for e in &self.program.elements {
+            if let Element::Statement(TopLevelStatement::Declaration(d)) = e {
+                data.globals.push(d.name.clone());
+            }
+        }
+
+        self.program.optimize(&mut data)
+    }
+}
+
+pub struct OptimizerData {
+    pub globals: Vec<String>,
+    pub declared: Vec<String>,
+    pub used: Vec<String>,
+}
+
+pub trait Optimize: Sized {
+    fn optimize(self, data: &mut OptimizerData) -> Self;
+}
+
+impl Optimize for Program {
+    fn optimize(self, data: &mut OptimizerData) -> Self {
+        let mut program = Program::new();
+        for element in self.elements {
+            program.push(element.optimize(data));
+        }
+        program
+    }
+}
+
+impl Optimize for Element {
+    fn optimize(self, data: &mut OptimizerData) -> Self {
+        match self {
+            Element::Statement(s) => Element::Statement(s.optimize(data)),
+            Element::Function(f) => Element::Function(f.optimize(data)),
+            Element::Empty => Element::Empty,
+        }
+    }
+}
+
+impl Optimize for TopLevelStatement {
+    fn optimize(self, data: &mut OptimizerData) -> Self {
+        match self {
+            TopLevelStatement::Declaration(mut d) => {
+                d.value = d.value.map(|v| v.optimize(data));
+                TopLevelStatement::Declaration(d)
+            }
+            s => s
+        }
+    }
+}
+
+impl Optimize for Function {
+    fn optimize(mut self, data: &mut OptimizerData) -> Self {
+        data.declared.clear();
+        data.used.clear();
+        let statements = optimize_statements(self.body.statements, data);
+        self.body.statements = remove_unused(statements, data);
+        self
+    }
+}
+
+impl Optimize for Statement {
+    fn optimize(self, data: &mut OptimizerData) -> Self {
+        match self {
+            Statement::Block(b) => {
+                let statements = optimize_statements(b.statements, data);
+                if statements.is_empty() {
+                    Statement::Noop
+                }
+                else {
+                    Statement::Block(Block { statements })
+                }
+            }
+            Statement::Expression(e) => {
+                let e = e.optimize(data);
+                if e.has_side_effects() {
+                    e.collect_used(&mut data.used);
+                    Statement::Expression(e)
+                }
+                else {
+                    Statement::Noop
+                }
+            }
+            Statement::Declaration(mut d) => {
+                d.value = d.value.map(|v| v.optimize(data));
+                if let Some(v) = &d.value {
+                    v.collect_used(&mut data.used);
+                }
+                if !data.declared.contains(&d.name) {
+                    data.declared.push(d.name.clone());
+                }
+                Statement::Declaration(d)
+            }
+            Statement::Assignment(mut a) => {
+                a.value = a.value.optimize(data);
+                a.value.collect_used(&mut data.used);
+                Statement::Assignment(a)
+            }
+            Statement::If(i) => i.optimize(data),
+            Statement::While(w) => w.optimize(data),
+            Statement::For(f) => f.optimize(data),
+            Statement::Match(m) => m.optimize(data),
+            Statement::Return(r) => {
+                let r = r.map(|v| v.optimize(data));
+                if let Some(v) = &r {
+                    v.collect_used(&mut data.used);
+                }
+                Statement::Return(r)
+            }
+            s => s
+        }
+    }
+}
+
+impl IfStatement {
+    fn optimize(self, data: &mut OptimizerData) -> Statement {
+        let condition = self.condition.optimize(data);
+        match condition.truthiness() {
+            Some(true) => self.body.optimize(data),
+            Some(false) => self.else_body.map(|e| e.optimize(data)).unwrap_or(Statement::Noop),
+            None => {
+                condition.collect_used(&mut data.used);
+                let body = self.body.optimize(data);
+                let else_body = self.else_body.map(|e| e.optimize(data)).filter(|e| !matches!(e, Statement::Noop));
+                Statement::If(IfStatement {
+                    condition,
+                    body: Box::new(body),
+                    else_body: else_body.map(Box::new),
+                })
+            }
+        }
+    }
+}
+
+impl WhileStatement {
+    fn optimize(self, data: &mut OptimizerData) -> Statement {
+        let condition = self.condition.optimize(data);
+        if condition.truthiness() == Some(false) {
+            return Statement::Noop;
+        }
+        condition.collect_used(&mut data.used);
+        Statement::While(WhileStatement {
+            condition,
+            body: Box::new(self.body.optimize(data)),
+        })
+    }
+}
+
+impl ForStatement {
+    fn optimize(self, data: &mut OptimizerData) -> Statement {
+        let init = self.init.optimize(data);
+        let condition = self.condition.optimize(data);
+        if condition.truthiness() == Some(false) {
+            return init;
+        }
+        condition.collect_used(&mut data.used);
+        Statement::For(ForStatement {
+            init: Box::new(init),
+            condition,
+            next: Box::new(self.next.optimize(data)),
+            body: Box::new(self.body.optimize(data)),
+        })
+    }
+}
+
+impl MatchStatement {
+    fn optimize(self, data: &mut OptimizerData) -> Statement {
+        let value = self.value.optimize(data);
+        value.collect_used(&mut data.used);
+        let arms = self.arms.into_iter().map(|arm| MatchArm {
+            patterns: arm.patterns,
+            body: Box::new(arm.body.optimize(data)),
+        }).collect();
+        Statement::Match(MatchStatement {
+            value,
+            arms,
+            default: self.default.map(|d| Box::new(d.optimize(data))),
+        })
+    }
+}
+
+impl Optimize for Expression {
+    fn optimize(self, _data: &mut OptimizerData) -> Self {
+        self.fold()
+    }
+}
+
+impl Expression {
+    fn fold(self) -> Self {
+        match self {
+            Expression::Binary(mut b) => {
+                let left = b.left.fold();
+                let right = b.right.fold();
+                if b.operator == Operator::And || b.operator == Operator::Or {
+                    let short = b.operator == Operator::Or;
+                    match (left.truthiness(), right.truthiness()) {
+                        (Some(l), _) if l == short => return Expression::Literal(Literal::Bool(short)),
+                        (Some(_), Some(r)) => return Expression::Literal(Literal::Bool(r)),
+                        (Some(_), None) if right.infer_type() == Some(Type::Bool) => return right,
+                        _ => {}
+                    }
+                }
+                else if let (Expression::Literal(l), Expression::Literal(r)) = (&left, &right)
+                    && let Some(folded) = fold_binary(l, &b.operator, r) {
+                    return Expression::Literal(folded);
+                }
+                b.left = Box::new(left);
+                b.right = Box::new(right);
+                Expression::Binary(b)
+            }
+            Expression::Unary(mut u) => {
+                let expr = u.expr.fold();
+                if let Expression::Literal(l) = &expr {
+                    let folded = match (&u.operator, l) {
+                        (Operator::Minus, Literal::Integer(i)) => Some(Literal::Integer(i.wrapping_neg())),
+                        (Operator::Minus, Literal::Float(f)) => Some(Literal::Float(-f)),
+                        (Operator::Not, Literal::Integer(i)) => Some(Literal::Integer(!i)),
+                        (Operator::Not, Literal::Bool(b)) => Some(Literal::Bool(!b)),
+                        _ => None
+                    };
+                    if let Some(folded) = folded {
+                        return Expression::Literal(folded);
+                    }
+                }
+                u.expr = Box::new(expr);
+                Expression::Unary(u)
+            }
+            Expression::Call(mut c) => {
+                c.arguments = c.arguments.into_iter().map(|a| a.fold()).collect();
+                Expression::Call(c)
+            }
+            Expression::Argument(a) => Expression::Argument(Box::new(a.fold())),
+            e => e
+        }
+    }
+
+    fn truthiness(&self) -> Option<bool> {
+        if let Expression::Literal(l) = self {
+            Some(!literal_is_zero(l))
+        }
+        else {
+            None
+        }
+    }
+
+    fn has_side_effects(&self) -> bool {
+        match self {
+            Expression::Call(_) => true,
+            Expression::Binary(b) => b.left.has_side_effects() || b.right.has_side_effects(),
+            Expression::Unary(u) => u.expr.has_side_effects(),
+            Expression::Argument(a) => a.has_side_effects(),
+            _ => false
+        }
+    }
+
+    fn collect_used(&self, used: &mut Vec<String>) {
+        match self {
+            Expression::Identifier(i) => {
+                if !used.contains(i) {
+                    used.push(i.clone());
+                }
+            }
+            Expression::Binary(b) => {
+                b.left.collect_used(used);
+                b.right.collect_used(used);
+            }
+            Expression::Unary(u) => u.expr.collect_used(used),
+            Expression::Call(c) => {
+                for arg in &c.arguments {
+                    arg.collect_used(used);
+                }
+            }
+            Expression::Argument(a) => a.collect_used(used),
+            Expression::Literal(_) => {}
+        }
+    }
+}
+
+fn optimize_statements(statements: Vec<Statement>, data: &mut OptimizerData) -> Vec<Statement> {
+    let mut result = Vec::new();
+    for stmt in statements {
+        let stmt = stmt.optimize(data);
+        if matches!(stmt, Statement::Noop) {
+            continue;
+        }
+        let terminal = is_terminal(&stmt);
+        result.push(stmt);
+        if terminal {
+            break;
+        }
+    }
+    result
+}
+
+fn is_terminal(stmt: &Statement) -> bool {
+    match stmt {
+        Statement::Return(_) | Statement::Break | Statement::Continue => true,
+        Statement::Block(b) => b.statements.last().map(is_terminal).unwrap_or(false),
+        Statement::If(i) => is_terminal(&i.body) && i.else_body.as_ref().map(|e| is_terminal(e)).unwrap_or(false),
+        Statement::Match(m) => m.arms.iter().all(|a| is_terminal(&a.body)) && m.default.as_ref().map(|d| is_terminal(d)).unwrap_or(false),
+        _ => false
+    }
+}
+
+fn remove_unused(statements: Vec<Statement>, data: &OptimizerData) -> Vec<Statement> {
+    statements.into_iter().filter_map(|s| remove_unused_statement(s, data)).collect()
+}
+
+fn remove_unused_statement(stmt: Statement, data: &OptimizerData) -> Option<Statement> {
+    let unused = |name: &String| data.declared.contains(name) && !data.used.contains(name) && !data.globals.contains(name);
+    match stmt {
+        Statement::Declaration(d) if unused(&d.name) => {
+            d.value.filter(|v| v.has_side_effects()).map(Statement::Expression)
+        }
+        Statement::Assignment(a) if unused(&a.name) => {
+            Some(a.value).filter(|v| v.has_side_effects()).map(Statement::Expression)
+        }
+        Statement::Block(b) => {
+            let statements = remove_unused(b.statements, data);
+            if statements.is_empty() {
+                None
+            }
+            else {
+                Some(Statement::Block(Block { statements }))
+            }
+        }
+        Statement::If(mut i) => {
+            i.body = Box::new(remove_unused_statement(*i.body, data).unwrap_or(Statement::Noop));
+            i.else_body = i.else_body.and_then(|e| remove_unused_statement(*e, data)).map(Box::new);
+            Some(Statement::If(i))
+        }
+        Statement::Match(mut m) => {
+            for arm in &mut m.arms {
+                let body = std::mem::replace(&mut *arm.body, Statement::Noop);
+                *arm.body = remove_unused_statement(body, data).unwrap_or(Statement::Noop);
+            }
+            m.default = m.default.map(|d| Box::new(remove_unused_statement(*d, data).unwrap_or(Statement::Noop)));
+            Some(Statement::Match(m))
+        }
+        Statement::While(mut w) => {
+            w.body = Box::new(remove_unused_statement(*w.body, data).unwrap_or(Statement::Noop));
+            Some(Statement::While(w))
+        }
+        Statement::For(mut f) => {
+            f.init = Box::new(remove_unused_statement(*f.init, data).unwrap_or(Statement::Noop));
+            f.next = Box::new(remove_unused_statement(*f.next, data).unwrap_or(Statement::Noop));
+            f.body = Box::new(remove_unused_statement(*f.body, data).unwrap_or(Statement::Noop));
+            Some(Statement::For(f))
+        }
+        s => Some(s)
+    }
+}
+
+fn literal_is_zero(l: &Literal) -> bool {
+    match l {
+        Literal::Integer(i) => *i == 0,
+        Literal::Float(f) => *f == 0.0,
+        Literal::Char(c) => *c == '\0',
+        Literal::String(s) => s.is_empty(),
+        Literal::Bool(b) => !*b,
+        Literal::Null => true
+    }
+}
+
+fn fold_binary(left: &Literal, operator: &Operator, right: &Literal) -> Option<Literal> {
+    match operator {
+        Operator::Equal | Operator::NotEqual => {
+            let equal = if let Literal::Null = left {
+                matches!(right, Literal::Null)
+            }
+            else if let Literal::Null = right {
+                false
+            }
+            else if is_numeric_zero(left) {
+                literal_is_zero(right)
+            }
+            else if is_numeric_zero(right) {
+                literal_is_zero(left)
+            }
+            else {
+                compare(left, right)? == Some(Ordering::Equal)
+            };
+            Some(Literal::Bool(equal == (*operator == Operator::Equal)))
+        }
+        Operator::LessThan => Some(Literal::Bool(compare(left, right)? == Some(Ordering::Less))),
+        Operator::GreaterThan => Some(Literal::Bool(compare(left, right)? == Some(Ordering::Greater))),
+        Operator::LessOrEqual => Some(Literal::Bool(matches!(compare(left, right)?, Some(Ordering::Less | Ordering::Equal)))),
+        Operator::GreaterOrEqual => Some(Literal::Bool(matches!(compare(left, right)?, Some(Ordering::Greater | Ordering::Equal)))),
+        _ => fold_arithmetic(left, operator, right)
+    }
+}
+
+fn is_numeric_zero(l: &Literal) -> bool {
+    matches!(l, Literal::Integer(0)) || matches!(l, Literal::Float(f) if *f == 0.0)
+}
+
+/// Mirrors `Variable::compare` in the VM: `None` means the values cannot be compared,
+/// `Some(None)` means they are only known to be unequal.
+fn compare(left: &Literal, right: &Literal) -> Option<Option<Ordering>> {
+    match (left, right) {
+        (Literal::String(a), Literal::String(b)) => Some(if a == b { Some(Ordering::Equal) } else { None }),
+        (Literal::Integer(a), Literal::Integer(b)) => Some(Some(a.cmp(b))),
+        (Literal::Char(a), Literal::Char(b)) => Some(Some(a.cmp(b))),
+        (Literal::Integer(a), Literal::Char(b)) => Some(Some(a.cmp(&(*b as i64)))),
+        (Literal::Char(a), Literal::Integer(b)) => Some(Some((*a as u32).cmp(&(*b as u32)))),
+        (Literal::Integer(a), Literal::Float(b)) => Some((*a as f64).partial_cmp(b)),
+        (Literal::Char(a), Literal::Float(b)) => Some((*a as u32 as f64).partial_cmp(b)),
+        (Literal::Float(a), Literal::Float(b)) => Some(a.partial_cmp(b)),
+        (Literal::Float(a), Literal::Integer(b)) => Some(a.partial_cmp(&(*b as f64))),
+        (Literal::Bool(a), Literal::Bool(b)) => Some(if a == b { Some(Ordering::Equal) } else { None }),
+        (Literal::Bool(_), _) => Some(None),
+        (Literal::Null, Literal::Null) => Some(Some(Ordering::Equal)),
+        (Literal::Null, _) | (_, Literal::Null) => Some(None),
+        _ => None
+    }
+}
+
+/// Folds arithmetic the way the VM evaluates it: the left operand decides the resulting type,
+/// except when a string is involved, in which case both sides are concatenated.
+fn fold_arithmetic(left: &Literal, operator: &Operator, right: &Literal) -> Option<Literal> {
+    if *operator == Operator::Plus {
+        match (left, right) {
+            (Literal::String(a), Literal::String(b)) => return Some(Literal::String(format!("{}{}", a, b))),
+            (Literal::String(a), Literal::Integer(_) | Literal::Float(_) | Literal::Bool(_) | Literal::Null) => {
+                return Some(Literal::String(format!("{}{}", a, right)));
+            }
+            (Literal::Integer(_) | Literal::Float(_) | Literal::Bool(_) | Literal::Null, Literal::String(b)) => {
+                return Some(Literal::String(format!("{}{}", left, b)));
+            }
+            _ => {}
+        }
+    }
+    match left {
+        Literal::Integer(a) => {
+            let b = match right {
+                Literal::Integer(b) => *b,
+                Literal::Float(b) => *b as i64,
+                Literal::Char(b) => *b as i64,
+                _ => return None
+            };
+            fold_int(*a, operator, b).map(Literal::Integer)
+        }
+        Literal::Float(a) => {
+            let b = match right {
+                Literal::Integer(b) => *b as f64,
+                Literal::Float(b) => *b,
+                Literal::Char(b) => *b as u32 as f64,
+                _ => return None
+            };
+            match operator {
+                Operator::Plus => Some(Literal::Float(a + b)),
+                Operator::Minus => Some(Literal::Float(a - b)),
+                Operator::Multiply => Some(Literal::Float(a * b)),
+                Operator::Divide => Some(Literal::Float(a / b)),
+                Operator::Modulo => Some(Literal::Float(a % b)),
+                _ => None
+            }
+        }
+        Literal::Char(a) => {
+            let b = match right {
+                Literal::Integer(b) => *b as u32,
+                Literal::Float(b) => *b as u32,
+                Literal::Char(b) => *b as u32,
+                _ => return None
+            };
+            let c = fold_int(*a as u32 as i64, operator, b as i64)? as u32;
+            char::from_u32(c).map(Literal::Char)
+        }
+        Literal::Bool(a) => {
+            let Literal::Bool(b) = right else { return None; };
+            match operator {
+                Operator::BitwiseAnd => Some(Literal::Bool(a & b)),
+                Operator::BitwiseOr => Some(Literal::Bool(a | b)),
+                Operator::Xor => Some(Literal::Bool(a ^ b)),
+                _ => None
+            }
+        }
+        _ => None
+    }
+}
+
+fn fold_int(a: i64, operator: &Operator, b: i64) -> Option<i64> {
+    match operator {
+        Operator::Plus => Some(a.wrapping_add(b)),
+        Operator::Minus => Some(a.wrapping_sub(b)),
+        Operator::Multiply => Some(a.wrapping_mul(b)),
+        Operator::Divide => a.checked_div(b),
+        Operator::Modulo => a.checked_rem(b),
+        Operator::BitwiseAnd => Some(a & b),
+        Operator::BitwiseOr => Some(a | b),
+        Operator::Xor => Some(a ^ b),
+        Operator::LeftShift => Some(a.wrapping_shl(b as u32)),
+        Operator::LogicalRightShift => Some((a as u64).wrapping_shr(b as u32) as i64),
+        Operator::ArithmeticRightShift => Some(a.wrapping_shr(b as u32)),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::script::compiler::ast::{Element, Expression, Statement};
+    use crate::script::compiler::lexer::{Lexer, Literal, Operator};
+    use crate::script::compiler::optimizer::Optimizer;
+    use crate::script::compiler::parser::Parser;
+
+    fn main_body(code: &str) -> Vec<Statement> {
+        let program = Parser::new(Lexer::new(code.to_string())).parse().unwrap();
+        let program = Optimizer::new(program).optimize();
+        program.elements.into_iter().find_map(|e| match e {
+            Element::Function(f) if f.name == "main" => Some(f.body.statements),
+            _ => None
+        }).unwrap()
+    }
+
+    fn call_argument(stmt: &Statement) -> &Expression {
+        let Statement::Expression(Expression::Call(c)) = stmt else { panic!("expected a call, found {:?}", stmt) };
+        &c.arguments[0]
+    }
+
+    #[test]
+    fn folds_constants() {
+        let body = main_body("fn main() { print(1 + 2); print(\"a\" + 1); print(2 * 3 > 5); print(-(4 - 6)); }");
+        assert!(matches!(call_argument(&body[0]), Expression::Literal(Literal::Integer(3))));
+        assert!(matches!(call_argument(&body[1]), Expression::Literal(Literal::String(s)) if s == "a1"));
+        assert!(matches!(call_argument(&body[2]), Expression::Literal(Literal::Bool(true))));
+        assert!(matches!(call_argument(&body[3]), Expression::Literal(Literal::Integer(2))));
+    }
+
+    #[test]
+    fn folds_logic_only_to_bools() {
+        let body = main_body("fn main() { let x: int = 5; print(true && x > 1); print(false || x); print(true && x); print(false && x); }");
+        assert!(matches!(call_argument(&body[1]), Expression::Binary(b) if b.operator == Operator::GreaterThan));
+        assert!(matches!(call_argument(&body[2]), Expression::Binary(b) if b.operator == Operator::Or));
+        assert!(matches!(call_argument(&body[3]), Expression::Binary(b) if b.operator == Operator::And));
+        assert!(matches!(call_argument(&body[4]), Expression::Literal(Literal::Bool(false))));
+    }
+
+    #[test]
+    fn keeps_division_by_zero() {
+        let body = main_body("fn main() { print(1 / 0); }");
+        assert!(matches!(call_argument(&body[0]), Expression::Binary(_)));
+    }
+
+    #[test]
+    fn prunes_constant_branches() {
+        let body = main_body("fn main() { if true {} while false { print(1); } if 1 > 2 { print(2); } else { print(3); } }");
+        assert_eq!(body.len(), 1);
+        let Statement::Block(b) = &body[0] else { panic!("expected the else block, found {:?}", body[0]) };
+        assert!(matches!(call_argument(&b.statements[0]), Expression::Literal(Literal::Integer(3))));
+    }
+
+    #[test]
+    fn removes_code_after_return() {
+        let body = main_body("fn main() { print(1); return; print(2); }");
+        assert_eq!(body.len(), 2);
+        assert!(matches!(body[1], Statement::Return(None)));
+    }
+
+    #[test]
+    fn removes_unused_locals() {
+        let body = main_body("fn f() -> int { return 1; } fn main() { let x: int = 5; let y: int = 1; let z: int = f(); print(y); }");
+        assert_eq!(body.len(), 3);
+        assert!(matches!(&body[0], Statement::Declaration(d) if d.name == "y"));
+        assert!(matches!(&body[1], Statement::Expression(Expression::Call(c)) if c.function == "f"));
+    }
+
+    #[test]
+    fn keeps_globals() {
+        let body = main_body("let g: int = 0; fn main() { g = 1; }");
+        assert!(matches!(&body[0], Statement::Assignment(a) if a.name == "g"));
+    }
+}
@@ -8,7 +8,7 @@ pub struct Parser {
     program: Program,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ParseError {
     pub message: String,
 }
@@ -84,7 +84,16 @@ impl Parser {
                 },
                 Keyword::Let => Ok(Element::Statement(TopLevelStatement::Declaration(self.parse_declaration(true)?))),
                 Keyword::Fn => Ok(Element::Function(self.parse_fn()?)),
-                _ => Err(format!("File: Unexpected keyword, expected 'include' | 'use' | 'const' | 'let' | 'fn', found {}", keyword).into())
+                Keyword::Pub => {
+                    let token = self.lexer.next_token();
+                    if token != Token::Keyword(Keyword::Fn) {
+                        return Err(format!("Pub: Unexpected token, expected 'fn', found {}", token).into());
+                    }
+                    let mut function = self.parse_fn()?;
+                    function.public = true;
+                    Ok(Element::Function(function))
+                }
+                _ => Err(format!("File: Unexpected keyword, expected 'include' | 'use' | 'const' | 'let' | 'pub' | 'fn', found {}", keyword).into())
             }
         }
         else {
@@ -124,6 +133,7 @@ impl Parser {
     fn parse_declaration(&mut self, semi: bool) -> Result<Declaration, ParseError> {
         let token = self.lexer.next_token();
         if let Token::Identifier(name) = token {
+            check_name("Let/Const", &name)?;
             let mut ty = None;
             let mut token = self.lexer.next_token();
             if let Token::Colon = token {
@@ -200,6 +210,7 @@ impl Parser {
                     token = self.lexer.next_token();
                 }
                 if let Token::Identifier(name) = token {
+                    check_name("Fn", &name)?;
                     let token = self.lexer.next_token();
                     if token != Token::Colon {
                         return Err(format!("Fn: Unexpected token, expected ':', found {}", token).into());
@@ -248,6 +259,7 @@ impl Parser {
             }
             Ok(Function {
                 name,
+                public: false,
                 parameters,
                 return_type: ty,
                 body: Block {
@@ -320,6 +332,60 @@ impl Parser {
                             body: Box::new(body),
                         }))
                     }
+                    Keyword::Match => {
+                        if !semi {
+                            return Err("Match cannot be used inside a for initialization or next component!".into());
+                        }
+                        let value = self.parse_expression()?;
+                        let token = self.lexer.next_token();
+                        if token != Token::LCurly {
+                            return Err(format!("Match: Unexpected token, expected '{{', found {}", token).into());
+                        }
+                        let mut arms = Vec::new();
+                        let mut default = None;
+                        let mut token = self.lexer.next_token();
+                        while token != Token::RCurly {
+                            if token == Token::Comma {
+                                token = self.lexer.next_token();
+                                continue;
+                            }
+                            self.lexer.revert(token);
+                            let (patterns, wildcard) = self.parse_patterns()?;
+                            let next = self.lexer.next_token();
+                            let block = next == Token::LCurly;
+                            self.lexer.revert(next);
+                            let body = Box::new(self.parse_statement(block)?);
+                            if !block {
+                                let next = self.lexer.next_token();
+                                match next {
+                                    Token::Comma | Token::Semicolon => {}
+                                    Token::RCurly => self.lexer.revert(next),
+                                    _ => return Err(format!("Match: Unexpected token, expected ',' or '}}', found {}", next).into())
+                                }
+                            }
+                            if wildcard {
+                                if default.is_some() {
+                                    return Err("Match: Multiple default arms".into());
+                                }
+                                default = Some(body);
+                            }
+                            else {
+                                arms.push(MatchArm {
+                                    patterns,
+                                    body
+                                });
+                            }
+                            token = self.lexer.next_token();
+                        }
+                        if let Some(warning) = check_exhaustive(&arms, &default) {
+                            self.program.warnings.push(warning);
+                        }
+                        Ok(Statement::Match(MatchStatement {
+                            value,
+                            arms,
+                            default
+                        }))
+                    }
                     Keyword::Break => {
                         if !semi {
                             return Err("Break cannot be used inside a for initialization or next component!".into());
@@ -377,6 +443,9 @@ impl Parser {
             }
             Token::Identifier(name) => {
                 let next = self.lexer.next_token();
+                if matches!(next, Token::OperatorAssign(_) | Token::Operator(Operator::Assign)) {
+                    check_name("Assignment", &name)?;
+                }
                 if let Token::OperatorAssign(operator) = next {
                     let extra = self.parse_expression()?;
                     if semi {
@@ -485,10 +554,55 @@ impl Parser {
     }
 
     fn parse_primary_expression(&mut self) -> Result<Expression, ParseError> {
+        let mut expr = self.parse_operand()?;
+        let mut token = self.lexer.next_token();
+        while token == Token::Keyword(Keyword::As) {
+            let ty = match self.lexer.next_token() {
+                Token::Keyword(word) => Type::try_from(word)?,
+                Token::Identifier(name) if name == "string" => Type::String,
+                token => return Err(format!("Cast: Unexpected token, expected type, found {}", token).into()),
+            };
+            expr = conversion(ty, expr)?;
+            token = self.lexer.next_token();
+        }
+        self.lexer.revert(token);
+        Ok(expr)
+    }
+
+    fn parse_operand(&mut self) -> Result<Expression, ParseError> {
         let token = self.lexer.next_token();
         match token {
+            Token::Keyword(word) if Type::try_from(word.clone()).is_ok() => {
+                let token = self.lexer.next_token();
+                if token != Token::LParen {
+                    return Err(format!("Conversion: Unexpected token, expected '(', found {}", token).into());
+                }
+                let mut arguments = self.parse_arguments()?;
+                if arguments.len() != 1 {
+                    return Err(format!("Conversion: {}() takes exactly one argument, found {}", word, arguments.len()).into());
+                }
+                conversion(Type::try_from(word)?, arguments.pop().unwrap())
+            }
             Token::Keyword(word) if word == Keyword::Args => {
                 let token = self.lexer.next_token();
+                if token == Token::Dot {
+                    let token = self.lexer.next_token();
+                    if token != Token::Identifier("len".to_string()) {
+                        return Err(format!("Args: Unexpected token, expected 'len' after '.', found {}", token).into());
+                    }
+                    let token = self.lexer.next_token();
+                    if token != Token::LParen {
+                        return Err(format!("Args: Unexpected token, expected '(', found {}", token).into());
+                    }
+                    let token = self.lexer.next_token();
+                    if token != Token::RParen {
+                        return Err(format!("Args: Unexpected token, expected ')', found {}", token).into());
+                    }
+                    return Ok(Expression::Call(CallExpression {
+                        function: "arg_count".to_string(),
+                        arguments: Vec::new(),
+                    }));
+                }
                 if token != Token::LSquare {
                     return Err(format!("Args: Unexpected token, expected '[', found {}", token).into());
                 }
@@ -509,6 +623,21 @@ impl Parser {
             Token::Identifier(name) => {
                 let token = self.lexer.next_token();
                 match token {
+                    Token::DoubleColon => {
+                        let token = self.lexer.next_token();
+                        let Token::Identifier(function) = token else {
+                            return Err(format!("Call: Unexpected token, expected Identifier after '::', found {}", token).into());
+                        };
+                        let token = self.lexer.next_token();
+                        if token != Token::LParen {
+                            return Err(format!("Call: Unexpected token, expected '(', found {}", token).into());
+                        }
+                        let arguments = self.parse_arguments()?;
+                        Ok(Expression::Call(CallExpression {
+                            function: format!("{}.{}", name, function),
+                            arguments,
+                        }))
+                    }
                     Token::LParen => {
                         let arguments = self.parse_arguments()?;
                         Ok(Expression::Call(CallExpression {
@@ -518,7 +647,10 @@ impl Parser {
                     }
                     _ => {
                         self.lexer.revert(token);
-                        Ok(Expression::Identifier(name))
+                        match constant(&name) {
+                            Some(value) => Ok(Expression::Literal(Literal::Float(value))),
+                            None => Ok(Expression::Identifier(name))
+                        }
                     }
                 }
             }
@@ -535,6 +667,37 @@ impl Parser {
         }
     }
 
+    fn parse_patterns(&mut self) -> Result<(Vec<Literal>, bool), ParseError> {
+        let mut patterns = Vec::new();
+        let mut wildcard = false;
+        loop {
+            let token = self.lexer.next_token();
+            match token {
+                Token::Underscore => wildcard = true,
+                Token::Literal(literal) => patterns.push(literal),
+                Token::Operator(Operator::Minus) => {
+                    match self.lexer.next_token() {
+                        Token::Literal(Literal::Integer(i)) => patterns.push(Literal::Integer(-i)),
+                        Token::Literal(Literal::Float(f)) => patterns.push(Literal::Float(-f)),
+                        token => return Err(format!("Match: Unexpected token, expected number after '-', found {}", token).into())
+                    }
+                }
+                _ => return Err(format!("Match: Unexpected token, expected Literal or '_', found {}", token).into())
+            }
+            let token = self.lexer.next_token();
+            match token {
+                Token::Operator(Operator::BitwiseOr) => {}
+                Token::ThickArrow => {
+                    if wildcard && !patterns.is_empty() {
+                        return Err("Match: '_' cannot be combined with other patterns".into());
+                    }
+                    return Ok((patterns, wildcard));
+                }
+                _ => return Err(format!("Match: Unexpected token, expected '|' or '=>', found {}", token).into())
+            }
+        }
+    }
+
     fn parse_arguments(&mut self) -> Result<Vec<Expression>, ParseError> {
         let mut arguments = Vec::new();
         let mut token = self.lexer.next_token();
@@ -551,4 +714,61 @@ impl Parser {
             token = self.lexer.next_token();
         }
     }
-}
\ No newline at end of file
+}
+
+/// The value of the built-in float constant `name`, which is substituted wherever the name is read.
+fn constant(name: &str) -> Option<f64> {
+    match name {
+        "PI" => Some(std::f64::consts::PI),
+        "E" => Some(std::f64::consts::E),
+        _ => None
+    }
+}
+
+/// Rejects declaring or assigning a variable named like a built-in constant, which could never be read.
+fn check_name(context: &str, name: &str) -> Result<(), ParseError> {
+    if constant(name).is_some() {
+        return Err(format!("{}: '{}' is a reserved constant and cannot be used as a variable name", context, name).into());
+    }
+    Ok(())
+}
+
+/// Turns `expr as ty` and `ty(expr)` into a call of the conversion builtin for `ty`, rejecting conversions
+/// that can never succeed if the type of `expr` is known.
+fn conversion(ty: Type, expr: Expression) -> Result<Expression, ParseError> {
+    let function = match ty {
+        Type::Int => "int",
+        Type::Float => "float",
+        Type::Char => "char",
+        Type::String => "string",
+        Type::Bool => "bool",
+        _ => return Err(format!("Conversion: Cannot convert to {}", ty).into())
+    };
+    if let Some(from) = expr.infer_type() && !from.converts_to(&ty) {
+        return Err(format!("Conversion: Cannot convert {} to {}", from, ty).into());
+    }
+    Ok(Expression::Call(CallExpression {
+        function: function.to_string(),
+        arguments: vec![expr],
+    }))
+}
+
+fn check_exhaustive(arms: &[MatchArm], default: &Option<Box<Statement>>) -> Option<ParseError> {
+    if default.is_some() {
+        return None;
+    }
+    let patterns = arms.iter().flat_map(|a| a.patterns.iter());
+    let mut bools = [false, false];
+    for pattern in patterns {
+        if let Literal::Bool(b) = pattern {
+            bools[*b as usize] = true;
+        }
+        else {
+            return None;
+        }
+    }
+    if !arms.is_empty() && bools != [true, true] {
+        return Some(format!("Match: Non-exhaustive match on bool, missing {}", if bools[1] { "false" } else { "true" }).into());
+    }
+    None
+}
@@ -0,0 +1,384 @@
+/// A JSON value. Objects keep the order of their keys.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+/// Parses a JSON document. Numbers without a fraction or exponent that fit into an `i64` are parsed as
+/// integers. Errors are returned as `line <line>, column <column>: <message>`.
+pub fn parse(s: &str) -> Result<Json, String> {
+    let mut parser = Parser {
+        chars: s.chars().collect(),
+        pos: 0,
+    };
+    let value = parser.value().and_then(|value| {
+        parser.whitespace();
+        match parser.peek() {
+            None => Ok(value),
+            Some(c) => Err(format!("Unexpected '{}' after the end of the document", c)),
+        }
+    });
+    value.map_err(|e| {
+        let before = &parser.chars[..parser.pos.min(parser.chars.len())];
+        let line = before.iter().filter(|c| **c == '\n').count() + 1;
+        let column = before.iter().rev().take_while(|c| **c != '\n').count() + 1;
+        format!("line {}, column {}: {}", line, column, e)
+    })
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn whitespace(&mut self) {
+        while self.peek().is_some_and(|c| matches!(c, ' ' | '\t' | '\n' | '\r')) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        self.whitespace();
+        match self.peek() {
+            Some(found) if found == c => {
+                self.pos += 1;
+                Ok(())
+            }
+            Some(found) => Err(format!("Expected '{}', found '{}'", c, found)),
+            None => Err(format!("Expected '{}', found the end of the document", c)),
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.whitespace();
+        match self.peek() {
+            Some('{') => self.object(),
+            Some('[') => self.array(),
+            Some('"') => self.string().map(Json::String),
+            Some('-' | '0'..='9') => self.number(),
+            Some('t') => self.keyword("true", Json::Bool(true)),
+            Some('f') => self.keyword("false", Json::Bool(false)),
+            Some('n') => self.keyword("null", Json::Null),
+            Some(c) => Err(format!("Unexpected '{}', expected a value", c)),
+            None => Err("Unexpected end of the document, expected a value".to_string()),
+        }
+    }
+
+    fn keyword(&mut self, word: &str, value: Json) -> Result<Json, String> {
+        for expected in word.chars() {
+            if self.peek() != Some(expected) {
+                return Err(format!("Invalid literal, expected \"{}\"", word));
+            }
+            self.pos += 1;
+        }
+        Ok(value)
+    }
+
+    fn object(&mut self) -> Result<Json, String> {
+        self.pos += 1;
+        let mut entries = Vec::new();
+        self.whitespace();
+        if self.peek() == Some('}') {
+            self.pos += 1;
+            return Ok(Json::Object(entries));
+        }
+        loop {
+            self.whitespace();
+            if self.peek() != Some('"') {
+                return Err("Expected a string as object key".to_string());
+            }
+            let key = self.string()?;
+            self.expect(':')?;
+            let value = self.value()?;
+            entries.retain(|(k, _)| *k != key);
+            entries.push((key, value));
+            self.whitespace();
+            match self.peek() {
+                Some(',') => self.pos += 1,
+                Some('}') => {
+                    self.pos += 1;
+                    return Ok(Json::Object(entries));
+                }
+                Some(c) => return Err(format!("Expected ',' or '}}', found '{}'", c)),
+                None => return Err("Unterminated object".to_string()),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Json, String> {
+        self.pos += 1;
+        let mut elements = Vec::new();
+        self.whitespace();
+        if self.peek() == Some(']') {
+            self.pos += 1;
+            return Ok(Json::Array(elements));
+        }
+        loop {
+            elements.push(self.value()?);
+            self.whitespace();
+            match self.peek() {
+                Some(',') => self.pos += 1,
+                Some(']') => {
+                    self.pos += 1;
+                    return Ok(Json::Array(elements));
+                }
+                Some(c) => return Err(format!("Expected ',' or ']', found '{}'", c)),
+                None => return Err("Unterminated array".to_string()),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.pos += 1;
+        let mut buffer = String::new();
+        loop {
+            let Some(c) = self.peek() else {
+                return Err("Unterminated string".to_string());
+            };
+            self.pos += 1;
+            match c {
+                '"' => return Ok(buffer),
+                '\\' => {
+                    let Some(e) = self.peek() else {
+                        return Err("Unterminated string".to_string());
+                    };
+                    self.pos += 1;
+                    buffer.push(match e {
+                        '"' | '\\' | '/' => e,
+                        'b' => '\x08',
+                        'f' => '\x0c',
+                        'n' => '\n',
+                        'r' => '\r',
+                        't' => '\t',
+                        'u' => self.unicode()?,
+                        _ => {
+                            self.pos -= 2;
+                            return Err(format!("Invalid escape sequence \\{}", e));
+                        }
+                    });
+                }
+                c if c.is_control() => {
+                    self.pos -= 1;
+                    return Err("Control characters must be escaped in strings".to_string());
+                }
+                c => buffer.push(c),
+            }
+        }
+    }
+
+    /// Reads the four hex digits after `\u`, combining surrogate pairs into one character.
+    fn unicode(&mut self) -> Result<char, String> {
+        let high = self.hex()?;
+        if !(0xD800..0xDC00).contains(&high) {
+            return char::from_u32(high).ok_or_else(|| "Invalid unicode escape".to_string());
+        }
+        if self.chars.get(self.pos..self.pos + 2) != Some(&['\\', 'u']) {
+            return Err("Expected a low surrogate after a high surrogate".to_string());
+        }
+        self.pos += 2;
+        let low = self.hex()?;
+        if !(0xDC00..0xE000).contains(&low) {
+            return Err("Invalid low surrogate".to_string());
+        }
+        char::from_u32(0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)).ok_or_else(|| "Invalid unicode escape".to_string())
+    }
+
+    fn hex(&mut self) -> Result<u32, String> {
+        let hex = self.chars.get(self.pos..self.pos + 4).map(|h| h.iter().collect::<String>()).unwrap_or_default();
+        let value = u32::from_str_radix(&hex, 16).map_err(|_| "Expected four hex digits after \\u".to_string())?;
+        self.pos += 4;
+        Ok(value)
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.pos;
+        let digits = |parser: &mut Parser| {
+            let start = parser.pos;
+            while parser.peek().is_some_and(|c| c.is_ascii_digit()) {
+                parser.pos += 1;
+            }
+            parser.pos > start
+        };
+        if self.peek() == Some('-') {
+            self.pos += 1;
+        }
+        if self.peek() == Some('0') {
+            self.pos += 1;
+        }
+        else if !digits(self) {
+            return Err("Expected a digit".to_string());
+        }
+        let mut float = false;
+        if self.peek() == Some('.') {
+            self.pos += 1;
+            float = true;
+            if !digits(self) {
+                return Err("Expected a digit after the decimal point".to_string());
+            }
+        }
+        if self.peek().is_some_and(|c| c == 'e' || c == 'E') {
+            self.pos += 1;
+            float = true;
+            if self.peek().is_some_and(|c| c == '+' || c == '-') {
+                self.pos += 1;
+            }
+            if !digits(self) {
+                return Err("Expected a digit in the exponent".to_string());
+            }
+        }
+        let number = self.chars[start..self.pos].iter().collect::<String>();
+        if !float && let Ok(i) = number.parse() {
+            return Ok(Json::Int(i));
+        }
+        number.parse().map(Json::Float).map_err(|_| format!("Invalid number {}", number))
+    }
+}
+
+/// Serialises a JSON value, on one line or indented by two spaces if `pretty` is set. Floats that are not
+/// finite are written as `null`.
+pub fn stringify(json: &Json, pretty: bool) -> String {
+    let mut out = String::new();
+    write(json, pretty, 0, &mut out);
+    out
+}
+
+fn write(json: &Json, pretty: bool, depth: usize, out: &mut String) {
+    match json {
+        Json::Null => out.push_str("null"),
+        Json::Bool(b) => out.push_str(&b.to_string()),
+        Json::Int(i) => out.push_str(&i.to_string()),
+        Json::Float(f) if !f.is_finite() => out.push_str("null"),
+        Json::Float(f) if f.fract() == 0.0 && f.abs() < 1e15 => out.push_str(&format!("{:.1}", f)),
+        Json::Float(f) => out.push_str(&f.to_string()),
+        Json::String(s) => string(s, out),
+        Json::Array(elements) => {
+            if elements.is_empty() {
+                out.push_str("[]");
+                return;
+            }
+            out.push('[');
+            for (i, element) in elements.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                indent(pretty, depth + 1, out);
+                write(element, pretty, depth + 1, out);
+            }
+            indent(pretty, depth, out);
+            out.push(']');
+        }
+        Json::Object(entries) => {
+            if entries.is_empty() {
+                out.push_str("{}");
+                return;
+            }
+            out.push('{');
+            for (i, (key, value)) in entries.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                indent(pretty, depth + 1, out);
+                string(key, out);
+                out.push_str(if pretty { ": " } else { ":" });
+                write(value, pretty, depth + 1, out);
+            }
+            indent(pretty, depth, out);
+            out.push('}');
+        }
+    }
+}
+
+fn indent(pretty: bool, depth: usize, out: &mut String) {
+    if pretty {
+        out.push('\n');
+        out.push_str(&"  ".repeat(depth));
+    }
+}
+
+fn string(s: &str, out: &mut String) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::script::json::{parse, stringify, Json};
+
+    #[test]
+    fn round_trips_nested_values() {
+        let text = r#"{"name":"mvc","tags":["a",{"deep":[1,2.5,null,true]},[]],"empty":{},"escaped":"a\"b\\c\nd\u0001"}"#;
+        let json = parse(text).unwrap();
+        assert_eq!(stringify(&json, false), text);
+        assert_eq!(parse(&stringify(&json, true)).unwrap(), json);
+    }
+
+    #[test]
+    fn pretty_prints() {
+        let json = parse(r#"{"a":[1,{"b":null}],"c":{}}"#).unwrap();
+        assert_eq!(stringify(&json, true), "{\n  \"a\": [\n    1,\n    {\n      \"b\": null\n    }\n  ],\n  \"c\": {}\n}");
+    }
+
+    #[test]
+    fn keeps_key_order_and_last_duplicate() {
+        let json = parse(r#"{"b":1,"a":2,"b":3}"#).unwrap();
+        assert_eq!(json, Json::Object(vec![("a".to_string(), Json::Int(2)), ("b".to_string(), Json::Int(3))]));
+    }
+
+    #[test]
+    fn classifies_numbers() {
+        assert_eq!(parse("1").unwrap(), Json::Int(1));
+        assert_eq!(parse("-0").unwrap(), Json::Int(0));
+        assert_eq!(parse("1.0").unwrap(), Json::Float(1.0));
+        assert_eq!(parse("1e3").unwrap(), Json::Float(1000.0));
+        assert_eq!(parse("-2.5E-1").unwrap(), Json::Float(-0.25));
+        assert_eq!(parse("9223372036854775808").unwrap(), Json::Float(9223372036854775808.0));
+        assert_eq!(stringify(&Json::Float(1.0), false), "1.0");
+        assert_eq!(stringify(&Json::Float(f64::NAN), false), "null");
+    }
+
+    #[test]
+    fn decodes_surrogate_pairs() {
+        assert_eq!(parse(r#""\uD83D\uDE00""#).unwrap(), Json::String("😀".to_string()));
+        assert_eq!(parse(r#""\u00e9""#).unwrap(), Json::String("é".to_string()));
+    }
+
+    #[test]
+    fn rejects_lone_surrogates() {
+        assert_eq!(parse(r#""\uD83D""#).unwrap_err(), "line 1, column 8: Expected a low surrogate after a high surrogate");
+        assert_eq!(parse(r#""\uD83Dx""#).unwrap_err(), "line 1, column 8: Expected a low surrogate after a high surrogate");
+        assert_eq!(parse(r#""\uD83D\u0041""#).unwrap_err(), "line 1, column 14: Invalid low surrogate");
+        assert_eq!(parse(r#""\uDE00""#).unwrap_err(), "line 1, column 8: Invalid unicode escape");
+    }
+
+    #[test]
+    fn reports_error_positions() {
+        assert_eq!(parse("{\n  \"a\": 1,\n  \"b\": }").unwrap_err(), "line 3, column 8: Unexpected '}', expected a value");
+        assert_eq!(parse("[1, 2\n  3]").unwrap_err(), "line 2, column 3: Expected ',' or ']', found '3'");
+        assert_eq!(parse("\"a\\qb\"").unwrap_err(), "line 1, column 3: Invalid escape sequence \\q");
+        assert_eq!(parse("[1] x").unwrap_err(), "line 1, column 5: Unexpected 'x' after the end of the document");
+        assert_eq!(parse("tru").unwrap_err(), "line 1, column 4: Invalid literal, expected \"true\"");
+        assert_eq!(parse("").unwrap_err(), "line 1, column 1: Unexpected end of the document, expected a value");
+    }
+}
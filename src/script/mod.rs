@@ -1,4 +1,6 @@
 pub mod compiler;
 pub mod run;
 pub mod assembly;
+pub mod cache;
+pub mod json;
 pub mod utils;
\ No newline at end of file
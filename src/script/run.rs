@@ -1,9 +1,20 @@
+use std::env;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::{Read, Write};
 use std::mem;
-use std::process::Command;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::UNIX_EPOCH;
 use bytebuffer::ByteBuffer;
 use mvutils::save::Loader;
 use mvutils::unsafe_multi_borrow_mut;
 use crate::script::assembly::consts::*;
+use crate::script::json;
+use crate::script::json::Json;
+use crate::script::utils::date;
 
 fn err(str: String) {
     eprintln!("{}", str);
@@ -246,7 +257,8 @@ fn get_jmp(buffer: &mut ByteBuffer, variables: &[Variable], addr_table: &[usize]
     }
 }
 
-pub fn run(code: &[u8], args: Vec<String>) {
+/// Runs the program and returns its exit code, which is the integer returned by `main`, or 0.
+pub fn run(code: &[u8], args: Vec<String>) -> i32 {
     let mut buffer = ByteBuffer::from_bytes(code);
     let mut variables: Vec<Variable> = Vec::new();
     let mut call_stack: Vec<usize> = Vec::new();
@@ -254,6 +266,10 @@ pub fn run(code: &[u8], args: Vec<String>) {
     let mut addr_table: Vec<usize> = Vec::new();
     let mut cmp = Cmp::Empty;
     let mut ret = Variable::Null;
+    let mut builtins = Builtins {
+        args: args.len(),
+        ..Default::default()
+    };
 
     let main = buffer.pop_u32().unwrap();
     if main >= buffer.len() as u32 {
@@ -283,9 +299,7 @@ pub fn run(code: &[u8], args: Vec<String>) {
         match codec {
             NOOP => {}
             END => {
-                let value = ret.int_or(0);
-                println!("Program exited with code {}", value);
-                break;
+                return ret.int_or(0) as i32;
             },
             MOV => {
                 let pos = buffer.get_rpos();
@@ -385,7 +399,7 @@ pub fn run(code: &[u8], args: Vec<String>) {
                 let pos = buffer.get_rpos();
                 let ident = buffer.pop_u8().unwrap() as char;
                 if ident == BUILTIN {
-                    call_function(buffer.pop_u32().unwrap(), &mut arg_stack);
+                    call_function(buffer.pop_u32().unwrap(), &mut arg_stack, &mut ret, &mut builtins);
                 }
                 else {
                     buffer.set_rpos(pos);
@@ -396,9 +410,7 @@ pub fn run(code: &[u8], args: Vec<String>) {
             }
             RET => {
                 if call_stack.is_empty() {
-                    let value = ret.int_or(0);
-                    println!("Program exited with code {}", value);
-                    break;
+                    return ret.int_or(0) as i32;
                 }
                 let addr = call_stack.pop().unwrap();
                 buffer.set_rpos(addr);
@@ -497,7 +509,8 @@ pub fn run(code: &[u8], args: Vec<String>) {
             }
             SH => {
                 let str = get_str(&mut buffer, &args, &variables);
-                Command::new("sh").arg("-c").arg(format!("{}", str)).spawn().unwrap().wait().unwrap();
+                let result = shell(&str, None, false);
+                ret = builtins.check("sh", result).map(|(code, _)| Variable::Int(code)).unwrap_or(Variable::Null);
             }
             PUSH_RET => {
                 ret = parse_variable(&mut buffer, &mut variables, &args, false);
@@ -509,6 +522,41 @@ pub fn run(code: &[u8], args: Vec<String>) {
                 }
                 variables[id] = ret.take();
             }
+            PRINT_INLINE => {
+                let str = get_str_any(&mut buffer, &args, &variables);
+                print!("{}", str);
+                io::stdout().flush().unwrap();
+            }
+            EPRINT => {
+                let str = get_str_any(&mut buffer, &args, &variables);
+                eprintln!("{}", str);
+            }
+            READ_LINE | READ_ALL => {
+                let id = buffer.pop_u32().unwrap() as usize;
+                if variables.len() <= id {
+                    variables.resize(id + 1, Variable::Null);
+                }
+                let mut input = String::new();
+                let read = if codec == READ_LINE {
+                    io::stdin().read_line(&mut input)
+                }
+                else {
+                    io::stdin().read_to_string(&mut input)
+                };
+                variables[id] = match read {
+                    Ok(0) if codec == READ_LINE => Variable::Null,
+                    Ok(_) if codec == READ_LINE => Variable::String(input.trim_end_matches(['\n', '\r']).to_string()),
+                    Ok(_) => Variable::String(input),
+                    Err(_) => Variable::Null
+                };
+            }
+            FORMAT => {
+                let id = buffer.pop_u32().unwrap() as usize;
+                if variables.len() <= id {
+                    variables.resize(id + 1, Variable::Null);
+                }
+                variables[id] = Variable::String(format(&mut arg_stack));
+            }
             CPY => {
                 let pos = buffer.get_rpos();
                 let ident = buffer.pop_u8().unwrap() as char;
@@ -533,6 +581,7 @@ pub fn run(code: &[u8], args: Vec<String>) {
             _ => err(format!("Unknown codec: {}!", codec)),
         }
     }
+    ret.int_or(0) as i32
 }
 
 #[derive(Clone, PartialEq, Debug, Default)]
@@ -542,6 +591,9 @@ enum Variable {
     Int(i64),
     Float(f64),
     Bool(bool),
+    List(Vec<Variable>),
+    /// A map from strings to values, which keeps the order its keys were added in.
+    Map(Vec<(String, Variable)>),
     Reference(*mut Variable),
     #[default]
     Null
@@ -559,6 +611,8 @@ impl Variable {
             Variable::Float(f) => *f == 0.0,
             Variable::Bool(b) => !*b,
             Variable::Char(c) => *c == 0,
+            Variable::List(l) => l.is_empty(),
+            Variable::Map(m) => m.is_empty(),
             Variable::Reference(ptr) => unsafe { (*ptr).as_ref().unwrap().is_zero() }
             Variable::Null => true
         }
@@ -623,6 +677,49 @@ impl Variable {
         }
     }
 
+    /// The value as an `Int` or `Float`, with characters converted to integers.
+    fn number(&self) -> Variable {
+        match self {
+            Variable::Int(_) | Variable::Float(_) => self.clone(),
+            Variable::Char(c) => Variable::Int(*c as i64),
+            Variable::Reference(ptr) => unsafe { (*ptr).as_ref().unwrap().number() }
+            _ => {
+                err("Variable is not a number!".to_string());
+                Variable::Int(0)
+            }
+        }
+    }
+
+    fn to_f64(&self) -> f64 {
+        match self.number() {
+            Variable::Float(f) => f,
+            v => v.int() as f64
+        }
+    }
+
+    /// The name of the type of the value, as it is written in MVScript.
+    fn type_name(&self) -> &'static str {
+        match self {
+            Variable::String(_) => "String",
+            Variable::Char(_) => "char",
+            Variable::Int(_) => "int",
+            Variable::Float(_) => "float",
+            Variable::Bool(_) => "bool",
+            Variable::List(_) => "List",
+            Variable::Map(_) => "Map",
+            Variable::Reference(ptr) => unsafe { (*ptr).as_ref().unwrap().type_name() }
+            Variable::Null => "null"
+        }
+    }
+
+    /// The value a reference points to, or the variable itself if it is not a reference.
+    fn dereference_or_self(&self) -> Variable {
+        match self {
+            Variable::Reference(ptr) => unsafe { (*ptr).as_ref().unwrap().dereference_or_self() }
+            _ => self.clone()
+        }
+    }
+
     fn not_null(&self) -> &Variable {
         match self {
             Variable::Null => {
@@ -675,6 +772,54 @@ impl Variable {
         }
     }
 
+    fn list(&self) -> Vec<Variable> {
+        match self {
+            Variable::List(l) => l.clone(),
+            Variable::Reference(ptr) => unsafe { (*ptr).as_ref().unwrap().list() }
+            _ => {
+                err("Variable is not a list!".to_string());
+                Vec::new()
+            }
+        }
+    }
+
+    fn map(&self) -> Vec<(String, Variable)> {
+        match self {
+            Variable::Map(m) => m.clone(),
+            Variable::Reference(ptr) => unsafe { (*ptr).as_ref().unwrap().map() }
+            _ => {
+                err("Variable is not a map!".to_string());
+                Vec::new()
+            }
+        }
+    }
+
+    fn to_json(&self) -> Json {
+        match self {
+            Variable::String(s) => Json::String(s.clone()),
+            Variable::Char(_) => Json::String(self.to_string()),
+            Variable::Int(i) => Json::Int(*i),
+            Variable::Float(f) => Json::Float(*f),
+            Variable::Bool(b) => Json::Bool(*b),
+            Variable::List(l) => Json::Array(l.iter().map(|v| v.to_json()).collect()),
+            Variable::Map(m) => Json::Object(m.iter().map(|(k, v)| (k.clone(), v.to_json())).collect()),
+            Variable::Reference(ptr) => unsafe { (*ptr).as_ref().unwrap().to_json() }
+            Variable::Null => Json::Null
+        }
+    }
+
+    fn from_json(json: Json) -> Variable {
+        match json {
+            Json::Null => Variable::Null,
+            Json::Bool(b) => Variable::Bool(b),
+            Json::Int(i) => Variable::Int(i),
+            Json::Float(f) => Variable::Float(f),
+            Json::String(s) => Variable::String(s),
+            Json::Array(a) => Variable::List(a.into_iter().map(Variable::from_json).collect()),
+            Json::Object(o) => Variable::Map(o.into_iter().map(|(k, v)| (k, Variable::from_json(v))).collect()),
+        }
+    }
+
     fn int(&self) -> i64 {
         match self {
             Variable::Int(i) => *i,
@@ -835,6 +980,34 @@ impl Variable {
                 }
                 _ => Cmp::NotEqual
             }
+            Variable::List(a) => match other {
+                Variable::List(b) => {
+                    if a == b {
+                        Cmp::Equal
+                    }
+                    else {
+                        Cmp::NotEqual
+                    }
+                }
+                Variable::Reference(ptr) => {
+                    self.compare(unsafe { (*ptr).as_ref().unwrap() })
+                }
+                _ => Cmp::NotEqual
+            }
+            Variable::Map(a) => match other {
+                Variable::Map(b) => {
+                    if a == b {
+                        Cmp::Equal
+                    }
+                    else {
+                        Cmp::NotEqual
+                    }
+                }
+                Variable::Reference(ptr) => {
+                    self.compare(unsafe { (*ptr).as_ref().unwrap() })
+                }
+                _ => Cmp::NotEqual
+            }
             Variable::Null => {
                 match other {
                     Variable::Null => Cmp::Equal,
@@ -893,6 +1066,7 @@ impl Variable {
                 Variable::Int(i) => i.to_string(),
                 Variable::Float(f) => f.to_string(),
                 Variable::Bool(b) => b.to_string(),
+                Variable::List(_) | Variable::Map(_) => self.to_string(),
                 Variable::Null => "null".to_string(),
                 _ => unreachable!()
             };
@@ -953,6 +1127,7 @@ impl Variable {
                 Variable::Int(i) => s.push_str(&i.to_string()),
                 Variable::Float(f) => s.push_str(&f.to_string()),
                 Variable::Bool(b) => s.push_str(&b.to_string()),
+                Variable::List(_) | Variable::Map(_) => s.push_str(&other.to_string()),
                 Variable::Reference(ptr) => self.add(unsafe { (*ptr).as_ref().unwrap() }),
                 Variable::Null => s.push_str("null")
             }
@@ -1471,6 +1646,8 @@ impl ToString for Variable {
             Variable::Int(i) => format!("{}", i),
             Variable::Float(f) => format!("{}", f),
             Variable::Bool(b) => format!("{}", b),
+            Variable::List(l) => format!("[{}]", l.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(", ")),
+            Variable::Map(m) => format!("{{{}}}", m.iter().map(|(k, v)| format!("{}: {}", k, v.to_string())).collect::<Vec<_>>().join(", ")),
             Variable::Reference(ptr) => unsafe { (*ptr).as_ref().unwrap().to_string() }
             Variable::Null => "null".to_string()
         }
@@ -1487,31 +1664,889 @@ enum Cmp {
     Less,
 }
 
-fn call_function(id: u32, stack: &mut Vec<Variable>) {
+/// Pops a format string from the stack and replaces every `{}` in it with the next value popped from the
+/// stack. `{{` and `}}` are written as `{` and `}`.
+fn format(stack: &mut Vec<Variable>) -> String {
+    let fmt = stack.pop().unwrap_or_default().not_null().string();
+    let mut out = String::new();
+    let mut chars = fmt.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('{', Some('}')) => {
+                chars.next();
+                let Some(value) = stack.pop() else {
+                    err(format!("Not enough arguments for format string \"{}\"!", fmt));
+                    break;
+                };
+                out.push_str(&value.to_string());
+            }
+            ('{', Some('{')) | ('}', Some('}')) => {
+                chars.next();
+                out.push(c);
+            }
+            _ => out.push(c)
+        }
+    }
+    out
+}
+
+/// Runs `cmd` with `sh -c` and returns its exit code, or -1 if it was killed by a signal. `input` is
+/// written to its stdin, otherwise stdin is inherited. If `capture` is set, stdout is returned instead
+/// of being inherited, without trailing newlines like in shell command substitution. Fails if the shell
+/// cannot be started or the input cannot be written, unless the command exits without reading all of it.
+fn shell(cmd: &str, input: Option<String>, capture: bool) -> io::Result<(i64, String)> {
+    let mut command = Command::new("sh");
+    command.arg("-c").arg(cmd);
+    if input.is_some() {
+        command.stdin(Stdio::piped());
+    }
+    if capture {
+        command.stdout(Stdio::piped());
+    }
+    let mut child = command.spawn()?;
+    let writer = input.map(|input| {
+        let mut stdin = child.stdin.take().unwrap();
+        thread::spawn(move || stdin.write_all(input.as_bytes()))
+    });
+    let output = child.wait_with_output()?;
+    if let Some(writer) = writer {
+        match writer.join().unwrap_or_else(|e| std::panic::resume_unwind(e)) {
+            Err(e) if e.kind() != io::ErrorKind::BrokenPipe => return Err(e),
+            _ => {}
+        }
+    }
+    Ok((output.status.code().unwrap_or(-1) as i64, String::from_utf8_lossy(&output.stdout).trim_end_matches('\n').to_string()))
+}
+
+/// The state of the builtin functions for one run of a program.
+#[derive(Default)]
+struct Builtins {
+    args: usize,
+    processes: Vec<Process>,
+    /// The error of the last fallible builtin, or null if it succeeded.
+    error: Variable,
+    /// The directory the git builtins run in, or the working directory of the VM if not set.
+    repository: Option<PathBuf>,
+}
+
+impl Builtins {
+    /// Records the outcome of a fallible operation on `path`, so the script can read it with `last_error`.
+    fn check<T>(&mut self, path: &str, result: io::Result<T>) -> Option<T> {
+        match result {
+            Ok(value) => {
+                self.error = Variable::Null;
+                Some(value)
+            }
+            Err(e) => {
+                self.error = Variable::String(format!("{}: {}", path, e));
+                None
+            }
+        }
+    }
+}
+
+impl Builtins {
+    /// Runs `f` on the process `id` and records its outcome for `last_error`, naming the program in errors.
+    fn process<T>(&mut self, id: Variable, f: impl FnOnce(&mut Process) -> io::Result<T>) -> Option<T> {
+        let process = process(&mut self.processes, id);
+        let program = process.command.get_program().to_string_lossy().to_string();
+        let result = f(process);
+        self.check(&program, result)
+    }
+
+    /// Runs git with `args` and returns its stdout, or `None` if it failed, in which case its error message
+    /// is recorded for `last_error`. With `echo`, the output of a successful command is printed as well.
+    fn git(&mut self, args: &[&str], echo: bool) -> Option<String> {
+        let mut command = Command::new("git");
+        if let Some(dir) = &self.repository {
+            command.current_dir(dir);
+        }
+        let output = command.args(args).output();
+        let output = self.check("git", output)?;
+        let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+        let stderr = String::from_utf8_lossy(&output.stderr).trim_end().to_string();
+        if !output.status.success() {
+            self.error = Variable::String(if !stderr.is_empty() {
+                stderr
+            }
+            else if !stdout.trim().is_empty() {
+                stdout.trim_end().to_string()
+            }
+            else {
+                format!("git {} failed with exit code {}", args.join(" "), output.status.code().unwrap_or(-1))
+            });
+            return None;
+        }
+        if echo {
+            print!("{}", stdout);
+            if !stderr.is_empty() {
+                eprintln!("{}", stderr);
+            }
+        }
+        Some(stdout)
+    }
+
+    /// Returns the result of converting `value` to `ty`, or null if it could not be converted, recording
+    /// the error for `last_error`.
+    fn convert(&mut self, value: &Variable, ty: &str, result: Option<Variable>) -> Variable {
+        match result {
+            Some(result) => {
+                self.error = Variable::Null;
+                result
+            }
+            None => {
+                self.error = Variable::String(match value {
+                    Variable::String(s) => format!("Cannot convert \"{}\" to {}", s, ty),
+                    _ => format!("Cannot convert {} {} to {}", value.type_name(), value.to_string(), ty),
+                });
+                Variable::Null
+            }
+        }
+    }
+}
+
+/// A process started by the `PROC_*` builtins, which refer to it by its index in the process table.
+/// `status`, `stdout` and `stderr` are set once the process has finished. `error` holds the reason the
+/// process could not be started or waited for, which is reported again until it is run again.
+struct Process {
+    command: Command,
+    capture: bool,
+    child: Option<Child>,
+    status: Option<i64>,
+    stdout: String,
+    stderr: String,
+    error: Option<String>,
+}
+
+impl Process {
+    fn new(program: String) -> Self {
+        Self {
+            command: Command::new(program),
+            capture: false,
+            child: None,
+            status: None,
+            stdout: String::new(),
+            stderr: String::new(),
+            error: None,
+        }
+    }
+
+    /// Starts the process, returning the error if it cannot be started.
+    fn spawn(&mut self) -> io::Result<()> {
+        if self.capture {
+            self.command.stdout(Stdio::piped()).stderr(Stdio::piped());
+        }
+        self.status = None;
+        self.stdout.clear();
+        self.stderr.clear();
+        self.error = None;
+        match self.command.spawn() {
+            Ok(child) => {
+                self.child = Some(child);
+                Ok(())
+            }
+            Err(e) => {
+                self.error = Some(e.to_string());
+                Err(e)
+            }
+        }
+    }
+
+    /// Waits for the process to finish and returns its exit code, or -1 if it was killed by a signal or
+    /// never started. Fails if the process could not be started or waited for.
+    fn wait(&mut self) -> io::Result<i64> {
+        if let Some(child) = self.child.take() {
+            match child.wait_with_output() {
+                Ok(output) => {
+                    self.status = Some(output.status.code().unwrap_or(-1) as i64);
+                    self.stdout = String::from_utf8_lossy(&output.stdout).to_string();
+                    self.stderr = String::from_utf8_lossy(&output.stderr).to_string();
+                }
+                Err(e) => {
+                    self.status = Some(-1);
+                    self.error = Some(e.to_string());
+                }
+            }
+        }
+        match &self.error {
+            Some(e) => Err(io::Error::other(e.clone())),
+            None => Ok(self.status.unwrap_or(-1)),
+        }
+    }
+
+    /// Returns the exit code of the process, or `None` while it is still running or if it was never started.
+    fn status(&mut self) -> io::Result<Option<i64>> {
+        if let Some(child) = &mut self.child && child.try_wait()?.is_some() {
+            self.wait()?;
+        }
+        match &self.error {
+            Some(e) => Err(io::Error::other(e.clone())),
+            None => Ok(self.status),
+        }
+    }
+}
+
+fn process(processes: &mut [Process], id: Variable) -> &mut Process {
+    let id = id.int();
+    if id < 0 || id as usize >= processes.len() {
+        err(format!("Unknown process id: {}", id));
+    }
+    &mut processes[id as usize]
+}
+
+/// Sets or removes an environment variable of the VM, which is inherited by the commands it runs. This
+/// is only unsafe in other threads reading the environment at the same time, but the VM runs on one thread.
+#[allow(unused_unsafe)]
+fn set_env(name: &str, value: Option<String>) {
+    unsafe {
+        match value {
+            Some(value) => env::set_var(name, value),
+            None => env::remove_var(name)
+        }
+    }
+}
+
+fn call_function(id: u32, stack: &mut Vec<Variable>, ret: &mut Variable, builtins: &mut Builtins) {
     match id {
         GIT_ADD_ALL => {
-            Command::new("git").arg("add").arg("*").spawn().unwrap().wait().unwrap();
+            *ret = Variable::Bool(builtins.git(&["add", "*"], true).is_some());
         }
         GIT_ADD => {
             let str = stack.pop().unwrap().not_null().string();
-            Command::new("git").arg("add").arg(str).spawn().unwrap().wait().unwrap();
+            *ret = Variable::Bool(builtins.git(&["add", &str], true).is_some());
         }
         GIT_COMMIT_DEFAULT => {
-            Command::new("git").arg("commit").arg("-m").arg("").spawn().unwrap().wait().unwrap();
+            let message = format!("committed at {}", date());
+            *ret = Variable::Bool(builtins.git(&["commit", "-m", &message], true).is_some());
         }
         GIT_COMMIT => {
             let str = stack.pop().unwrap().not_null().string();
-            Command::new("git").arg("commit").arg("-m").arg(str).spawn().unwrap().wait().unwrap();
+            *ret = Variable::Bool(builtins.git(&["commit", "-m", &str], true).is_some());
         }
         GIT_PUSH_UPSTREAM => {
-            Command::new("git").arg("push").spawn().unwrap().wait().unwrap();
+            *ret = Variable::Bool(builtins.git(&["push"], true).is_some());
         }
         GIT_PUSH => {
             let str = stack.pop().unwrap().not_null().string();
-            Command::new("git").arg("push").arg("-u").args(str.split(' ')).spawn().unwrap().wait().unwrap();
+            let mut args = vec!["push", "-u"];
+            args.extend(str.split(' '));
+            *ret = Variable::Bool(builtins.git(&args, true).is_some());
+        }
+        GIT_BRANCH => {
+            let branch = builtins.git(&["rev-parse", "--abbrev-ref", "HEAD"], false);
+            *ret = branch.map(|b| Variable::String(b.trim().to_string())).unwrap_or(Variable::Null);
+        }
+        GIT_STATUS => {
+            let status = builtins.git(&["status", "--porcelain"], false).map(|status| {
+                status.lines().filter(|l| l.len() > 3).map(|l| {
+                    let path = &l[3..];
+                    Variable::String(path.rsplit(" -> ").next().unwrap_or(path).to_string())
+                }).collect()
+            });
+            *ret = status.map(Variable::List).unwrap_or(Variable::Null);
+        }
+        GIT_LOG => {
+            let count = format!("-{}", stack.pop().unwrap().int().max(1));
+            let log = builtins.git(&["log", &count, "--format=%h %s"], false);
+            *ret = log.map(|log| Variable::List(log.lines().map(|l| Variable::String(l.to_string())).collect())).unwrap_or(Variable::Null);
+        }
+        GIT_DIFF_STATS => {
+            let stats = builtins.git(&["diff", "HEAD", "--numstat"], false).map(|diff| {
+                let (mut files, mut insertions, mut deletions) = (0, 0, 0);
+                for line in diff.lines() {
+                    let mut parts = line.split('\t');
+                    files += 1;
+                    insertions += parts.next().and_then(|n| n.parse::<i64>().ok()).unwrap_or(0);
+                    deletions += parts.next().and_then(|n| n.parse::<i64>().ok()).unwrap_or(0);
+                }
+                vec![Variable::Int(files), Variable::Int(insertions), Variable::Int(deletions)]
+            });
+            *ret = stats.map(Variable::List).unwrap_or(Variable::Null);
+        }
+        GIT_TAG => {
+            let str = stack.pop().unwrap().not_null().string();
+            *ret = Variable::Bool(builtins.git(&["tag", &str], true).is_some());
+        }
+        GIT_CHECKOUT => {
+            let str = stack.pop().unwrap().not_null().string();
+            *ret = Variable::Bool(builtins.git(&["checkout", &str], true).is_some());
+        }
+        GIT_FETCH => {
+            *ret = Variable::Bool(builtins.git(&["fetch"], true).is_some());
+        }
+        GIT_PULL => {
+            *ret = Variable::Bool(builtins.git(&["pull"], true).is_some());
+        }
+        SH_IN => {
+            let str = stack.pop().unwrap().not_null().string();
+            let input = stack.pop().unwrap().to_string();
+            let result = shell(&str, Some(input), false);
+            *ret = builtins.check("sh", result).map(|(code, _)| Variable::Int(code)).unwrap_or(Variable::Null);
+        }
+        SHOUT => {
+            let str = stack.pop().unwrap().not_null().string();
+            let result = shell(&str, None, true);
+            *ret = builtins.check("sh", result).map(|(_, out)| Variable::String(out)).unwrap_or(Variable::Null);
+        }
+        SHOUT_ERR => {
+            let str = stack.pop().unwrap().not_null().string();
+            let result = shell(&format!("exec 2>&1\n{}", str), None, true);
+            *ret = builtins.check("sh", result).map(|(_, out)| Variable::String(out)).unwrap_or(Variable::Null);
+        }
+        SHOUT_IN => {
+            let str = stack.pop().unwrap().not_null().string();
+            let input = stack.pop().unwrap().to_string();
+            let result = shell(&str, Some(input), true);
+            *ret = builtins.check("sh", result).map(|(_, out)| Variable::String(out)).unwrap_or(Variable::Null);
+        }
+        PROC => {
+            let str = stack.pop().unwrap().not_null().string();
+            builtins.processes.push(Process::new(str));
+            *ret = Variable::Int(builtins.processes.len() as i64 - 1);
+        }
+        PROC_ARG => {
+            let process = process(&mut builtins.processes, stack.pop().unwrap());
+            let arg = stack.pop().unwrap().to_string();
+            process.command.arg(arg);
+        }
+        PROC_ENV => {
+            let process = process(&mut builtins.processes, stack.pop().unwrap());
+            let key = stack.pop().unwrap().not_null().string();
+            let value = stack.pop().unwrap();
+            if value.is_null() {
+                process.command.env_remove(key);
+            }
+            else {
+                process.command.env(key, value.to_string());
+            }
+        }
+        PROC_CWD => {
+            let process = process(&mut builtins.processes, stack.pop().unwrap());
+            let dir = stack.pop().unwrap().not_null().string();
+            process.command.current_dir(dir);
+        }
+        PROC_CAPTURE => {
+            process(&mut builtins.processes, stack.pop().unwrap()).capture = true;
+        }
+        PROC_RUN => {
+            let status = builtins.process(stack.pop().unwrap(), |p| p.spawn().and_then(|_| p.wait()));
+            *ret = status.map(Variable::Int).unwrap_or(Variable::Null);
+        }
+        PROC_SPAWN => {
+            *ret = Variable::Bool(builtins.process(stack.pop().unwrap(), Process::spawn).is_some());
+        }
+        PROC_WAIT => {
+            let status = builtins.process(stack.pop().unwrap(), Process::wait);
+            *ret = status.map(Variable::Int).unwrap_or(Variable::Null);
+        }
+        PROC_OUTPUT => {
+            let output = builtins.process(stack.pop().unwrap(), |p| p.wait().map(|_| p.stdout.clone()));
+            *ret = output.map(Variable::String).unwrap_or(Variable::Null);
+        }
+        PROC_ERROR => {
+            let output = builtins.process(stack.pop().unwrap(), |p| p.wait().map(|_| p.stderr.clone()));
+            *ret = output.map(Variable::String).unwrap_or(Variable::Null);
+        }
+        PROC_STATUS => {
+            let status = builtins.process(stack.pop().unwrap(), Process::status);
+            *ret = status.flatten().map(Variable::Int).unwrap_or(Variable::Null);
+        }
+        READ_FILE => {
+            let path = stack.pop().unwrap().not_null().string();
+            let content = builtins.check(&path, fs::read_to_string(&path));
+            *ret = content.map(Variable::String).unwrap_or(Variable::Null);
+        }
+        WRITE_FILE => {
+            let path = stack.pop().unwrap().not_null().string();
+            let content = stack.pop().unwrap().to_string();
+            *ret = Variable::Bool(builtins.check(&path, fs::write(&path, content)).is_some());
+        }
+        APPEND_FILE => {
+            let path = stack.pop().unwrap().not_null().string();
+            let content = stack.pop().unwrap().to_string();
+            let result = OpenOptions::new().create(true).append(true).open(&path).and_then(|mut f| f.write_all(content.as_bytes()));
+            *ret = Variable::Bool(builtins.check(&path, result).is_some());
+        }
+        EXISTS => {
+            let path = stack.pop().unwrap().not_null().string();
+            *ret = Variable::Bool(Path::new(&path).exists());
+        }
+        IS_DIR => {
+            let path = stack.pop().unwrap().not_null().string();
+            *ret = Variable::Bool(Path::new(&path).is_dir());
+        }
+        LIST_DIR => {
+            let path = stack.pop().unwrap().not_null().string();
+            let entries = fs::read_dir(&path).and_then(|entries| {
+                entries.map(|e| e.map(|e| e.file_name().to_string_lossy().to_string())).collect::<io::Result<Vec<_>>>()
+            });
+            *ret = match builtins.check(&path, entries) {
+                Some(mut entries) => {
+                    entries.sort();
+                    Variable::List(entries.into_iter().map(Variable::String).collect())
+                }
+                None => Variable::Null
+            };
+        }
+        MKDIR => {
+            let path = stack.pop().unwrap().not_null().string();
+            *ret = Variable::Bool(builtins.check(&path, fs::create_dir_all(&path)).is_some());
+        }
+        REMOVE => {
+            let path = stack.pop().unwrap().not_null().string();
+            let result = if Path::new(&path).is_dir() {
+                fs::remove_dir_all(&path)
+            }
+            else {
+                fs::remove_file(&path)
+            };
+            *ret = Variable::Bool(builtins.check(&path, result).is_some());
+        }
+        COPY => {
+            let from = stack.pop().unwrap().not_null().string();
+            let to = stack.pop().unwrap().not_null().string();
+            *ret = Variable::Bool(builtins.check(&from, fs::copy(&from, &to)).is_some());
+        }
+        RENAME => {
+            let from = stack.pop().unwrap().not_null().string();
+            let to = stack.pop().unwrap().not_null().string();
+            *ret = Variable::Bool(builtins.check(&from, fs::rename(&from, &to)).is_some());
+        }
+        FILE_SIZE => {
+            let path = stack.pop().unwrap().not_null().string();
+            let size = builtins.check(&path, fs::metadata(&path)).map(|m| m.len() as i64);
+            *ret = size.map(Variable::Int).unwrap_or(Variable::Null);
+        }
+        MTIME => {
+            let path = stack.pop().unwrap().not_null().string();
+            let time = fs::metadata(&path).and_then(|m| m.modified());
+            let time = builtins.check(&path, time).map(|t| t.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs() as i64));
+            *ret = time.map(Variable::Int).unwrap_or(Variable::Null);
+        }
+        LAST_ERROR => {
+            *ret = builtins.error.clone();
+        }
+        LIST_NEW => {
+            *ret = Variable::List(Vec::new());
+        }
+        LIST_LEN => {
+            *ret = Variable::Int(stack.pop().unwrap().list().len() as i64);
+        }
+        LIST_GET => {
+            let list = stack.pop().unwrap().list();
+            let index = stack.pop().unwrap().int();
+            *ret = usize::try_from(index).ok().and_then(|i| list.get(i).cloned()).unwrap_or(Variable::Null);
+        }
+        LIST_PUSH => {
+            let mut list = stack.pop().unwrap().list();
+            list.push(stack.pop().unwrap());
+            *ret = Variable::List(list);
+        }
+        LEN => {
+            let value = stack.pop().unwrap();
+            *ret = Variable::Int(match value.dereference_or_self() {
+                Variable::List(l) => l.len(),
+                Variable::Map(m) => m.len(),
+                v => v.string_or_char().chars().count()
+            } as i64);
+        }
+        SUBSTRING => {
+            let str = stack.pop().unwrap().string_or_char();
+            let start = stack.pop().unwrap().int().max(0) as usize;
+            let end = stack.pop().unwrap();
+            let chars = str.chars().skip(start);
+            *ret = Variable::String(if end.is_null() {
+                chars.collect()
+            }
+            else {
+                chars.take((end.int().max(0) as usize).saturating_sub(start)).collect()
+            });
+        }
+        INDEX_OF => {
+            let str = stack.pop().unwrap().string_or_char();
+            let needle = stack.pop().unwrap().string_or_char();
+            *ret = Variable::Int(str.find(&needle).map_or(-1, |i| str[..i].chars().count() as i64));
+        }
+        SPLIT => {
+            let str = stack.pop().unwrap().string_or_char();
+            let separator = stack.pop().unwrap().string_or_char();
+            let parts = if separator.is_empty() {
+                str.chars().map(|c| Variable::String(c.to_string())).collect()
+            }
+            else {
+                str.split(&separator).map(|s| Variable::String(s.to_string())).collect()
+            };
+            *ret = Variable::List(parts);
+        }
+        JOIN => {
+            let list = stack.pop().unwrap().list();
+            let separator = stack.pop().unwrap().string_or_char();
+            *ret = Variable::String(list.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(&separator));
+        }
+        TRIM => {
+            *ret = Variable::String(stack.pop().unwrap().string_or_char().trim().to_string());
+        }
+        REPLACE => {
+            let str = stack.pop().unwrap().string_or_char();
+            let from = stack.pop().unwrap().string_or_char();
+            let to = stack.pop().unwrap().to_string();
+            *ret = Variable::String(if from.is_empty() { str } else { str.replace(&from, &to) });
+        }
+        UPPER => {
+            *ret = Variable::String(stack.pop().unwrap().string_or_char().to_uppercase());
+        }
+        LOWER => {
+            *ret = Variable::String(stack.pop().unwrap().string_or_char().to_lowercase());
+        }
+        STARTS_WITH => {
+            let str = stack.pop().unwrap().string_or_char();
+            let prefix = stack.pop().unwrap().string_or_char();
+            *ret = Variable::Bool(str.starts_with(&prefix));
+        }
+        ENDS_WITH => {
+            let str = stack.pop().unwrap().string_or_char();
+            let suffix = stack.pop().unwrap().string_or_char();
+            *ret = Variable::Bool(str.ends_with(&suffix));
+        }
+        CHAR_AT => {
+            let str = stack.pop().unwrap().string_or_char();
+            let index = stack.pop().unwrap().int();
+            let c = usize::try_from(index).ok().and_then(|i| str.chars().nth(i));
+            *ret = c.map(|c| Variable::Char(c as u32)).unwrap_or(Variable::Null);
+        }
+        REPEAT => {
+            let str = stack.pop().unwrap().string_or_char();
+            let times = stack.pop().unwrap().int().max(0) as usize;
+            *ret = Variable::String(str.repeat(times));
+        }
+        FORMAT_NUMBER => {
+            let value = stack.pop().unwrap();
+            let decimals = stack.pop().unwrap().int().max(0) as usize;
+            let value = match value.dereference_or_self() {
+                Variable::Int(i) => i as f64,
+                Variable::Char(c) => c as f64,
+                v => v.float()
+            };
+            *ret = Variable::String(format!("{:.*}", decimals, value));
+        }
+        TO_INT => {
+            let value = stack.pop().unwrap().dereference_or_self();
+            let result = match &value {
+                Variable::Int(i) => Some(*i),
+                Variable::Float(f) => Some(*f as i64),
+                Variable::Char(c) => Some(*c as i64),
+                Variable::Bool(b) => Some(*b as i64),
+                Variable::String(s) => s.trim().parse().ok(),
+                _ => None
+            };
+            *ret = builtins.convert(&value, "int", result.map(Variable::Int));
+        }
+        TO_FLOAT => {
+            let value = stack.pop().unwrap().dereference_or_self();
+            let result = match &value {
+                Variable::Int(i) => Some(*i as f64),
+                Variable::Float(f) => Some(*f),
+                Variable::String(s) => s.trim().parse().ok(),
+                _ => None
+            };
+            *ret = builtins.convert(&value, "float", result.map(Variable::Float));
+        }
+        TO_STRING => {
+            *ret = Variable::String(stack.pop().unwrap().to_string());
+        }
+        TO_CHAR => {
+            let value = stack.pop().unwrap().dereference_or_self();
+            let result = match &value {
+                Variable::Int(i) => u32::try_from(*i).ok().and_then(char::from_u32),
+                Variable::Char(c) => char::from_u32(*c),
+                Variable::String(s) => {
+                    let mut chars = s.chars();
+                    chars.next().filter(|_| chars.next().is_none())
+                }
+                _ => None
+            };
+            *ret = builtins.convert(&value, "char", result.map(|c| Variable::Char(c as u32)));
+        }
+        TO_BOOL => {
+            let value = stack.pop().unwrap().dereference_or_self();
+            let result = match &value {
+                Variable::Bool(b) => Some(*b),
+                Variable::Int(i) => Some(*i != 0),
+                Variable::String(s) => s.trim().parse().ok(),
+                _ => None
+            };
+            *ret = builtins.convert(&value, "bool", result.map(Variable::Bool));
+        }
+        TYPEOF => {
+            *ret = Variable::String(stack.pop().unwrap().type_name().to_string());
+        }
+        ABS => {
+            *ret = match stack.pop().unwrap().number() {
+                Variable::Int(i) => Variable::Int(i.wrapping_abs()),
+                v => Variable::Float(v.to_f64().abs())
+            };
+        }
+        MIN | MAX => {
+            let a = stack.pop().unwrap().number();
+            let b = stack.pop().unwrap().number();
+            let min = id == MIN;
+            *ret = match (a, b) {
+                (Variable::Int(a), Variable::Int(b)) => Variable::Int(if min { a.min(b) } else { a.max(b) }),
+                (a, b) => Variable::Float(if min { a.to_f64().min(b.to_f64()) } else { a.to_f64().max(b.to_f64()) })
+            };
+        }
+        CLAMP => {
+            let value = stack.pop().unwrap().number();
+            let low = stack.pop().unwrap().number();
+            let high = stack.pop().unwrap().number();
+            *ret = match (value, low, high) {
+                (Variable::Int(v), Variable::Int(l), Variable::Int(h)) => Variable::Int(v.max(l).min(h)),
+                (v, l, h) => Variable::Float(v.to_f64().max(l.to_f64()).min(h.to_f64()))
+            };
+        }
+        POW => {
+            let base = stack.pop().unwrap().number();
+            let exp = stack.pop().unwrap().number();
+            let int = match (&base, &exp) {
+                (Variable::Int(b), Variable::Int(e)) => u32::try_from(*e).ok().and_then(|e| b.checked_pow(e)),
+                _ => None
+            };
+            *ret = int.map(Variable::Int).unwrap_or_else(|| Variable::Float(base.to_f64().powf(exp.to_f64())));
+        }
+        FLOOR | CEIL | ROUND => {
+            *ret = match stack.pop().unwrap().number() {
+                Variable::Int(i) => Variable::Int(i),
+                v => {
+                    let f = v.to_f64();
+                    Variable::Float(match id {
+                        FLOOR => f.floor(),
+                        CEIL => f.ceil(),
+                        _ => f.round()
+                    })
+                }
+            };
+        }
+        SQRT => {
+            *ret = Variable::Float(stack.pop().unwrap().to_f64().sqrt());
+        }
+        SIN => {
+            *ret = Variable::Float(stack.pop().unwrap().to_f64().sin());
+        }
+        COS => {
+            *ret = Variable::Float(stack.pop().unwrap().to_f64().cos());
+        }
+        TAN => {
+            *ret = Variable::Float(stack.pop().unwrap().to_f64().tan());
+        }
+        ASIN => {
+            *ret = Variable::Float(stack.pop().unwrap().to_f64().asin());
+        }
+        ACOS => {
+            *ret = Variable::Float(stack.pop().unwrap().to_f64().acos());
+        }
+        ATAN => {
+            *ret = Variable::Float(stack.pop().unwrap().to_f64().atan());
+        }
+        LOG => {
+            *ret = Variable::Float(stack.pop().unwrap().to_f64().ln());
+        }
+        LOG10 => {
+            *ret = Variable::Float(stack.pop().unwrap().to_f64().log10());
+        }
+        EXP => {
+            *ret = Variable::Float(stack.pop().unwrap().to_f64().exp());
+        }
+        ATAN2 => {
+            let y = stack.pop().unwrap().to_f64();
+            let x = stack.pop().unwrap().to_f64();
+            *ret = Variable::Float(y.atan2(x));
+        }
+        ENV => {
+            let name = stack.pop().unwrap().not_null().string();
+            *ret = env::var(name).map(Variable::String).unwrap_or(Variable::Null);
+        }
+        SET_ENV => {
+            let name = stack.pop().unwrap().not_null().string();
+            let value = stack.pop().unwrap().to_string();
+            set_env(&name, Some(value));
+        }
+        UNSET_ENV => {
+            set_env(&stack.pop().unwrap().not_null().string(), None);
+        }
+        CWD => {
+            let dir = env::current_dir().map(|d| d.to_string_lossy().to_string());
+            *ret = builtins.check(".", dir).map(Variable::String).unwrap_or(Variable::Null);
+        }
+        CHDIR => {
+            let path = stack.pop().unwrap().not_null().string();
+            *ret = Variable::Bool(builtins.check(&path, env::set_current_dir(&path)).is_some());
+        }
+        ARG_COUNT => {
+            *ret = Variable::Int(builtins.args as i64);
+        }
+        PID => {
+            *ret = Variable::Int(std::process::id() as i64);
+        }
+        EXIT => {
+            std::process::exit(stack.pop().unwrap().int() as i32);
+        }
+        MAP_NEW => {
+            *ret = Variable::Map(Vec::new());
+        }
+        MAP_GET => {
+            let map = stack.pop().unwrap().map();
+            let key = stack.pop().unwrap().to_string();
+            *ret = map.into_iter().find(|(k, _)| *k == key).map(|(_, v)| v).unwrap_or(Variable::Null);
+        }
+        MAP_SET => {
+            let mut map = stack.pop().unwrap().map();
+            let key = stack.pop().unwrap().to_string();
+            let value = stack.pop().unwrap();
+            match map.iter_mut().find(|(k, _)| *k == key) {
+                Some((_, v)) => *v = value,
+                None => map.push((key, value))
+            }
+            *ret = Variable::Map(map);
+        }
+        MAP_HAS => {
+            let map = stack.pop().unwrap().map();
+            let key = stack.pop().unwrap().to_string();
+            *ret = Variable::Bool(map.iter().any(|(k, _)| *k == key));
+        }
+        MAP_KEYS => {
+            let map = stack.pop().unwrap().map();
+            *ret = Variable::List(map.into_iter().map(|(k, _)| Variable::String(k)).collect());
+        }
+        JSON_PARSE => {
+            let str = stack.pop().unwrap().not_null().string();
+            *ret = match json::parse(&str) {
+                Ok(json) => {
+                    builtins.error = Variable::Null;
+                    Variable::from_json(json)
+                }
+                Err(e) => {
+                    builtins.error = Variable::String(format!("Invalid JSON at {}", e));
+                    Variable::Null
+                }
+            };
+        }
+        JSON_STRINGIFY => {
+            let value = stack.pop().unwrap();
+            let pretty = stack.pop().unwrap().bool();
+            *ret = Variable::String(json::stringify(&value.to_json(), pretty));
+        }
+        GCD => {
+            let mut a = stack.pop().unwrap().int().unsigned_abs();
+            let mut b = stack.pop().unwrap().int().unsigned_abs();
+            while b != 0 {
+                (a, b) = (b, a % b);
+            }
+            *ret = Variable::Int(a as i64);
         }
         _ => {
             err(format!("Unknown built-in function id: {}", id));
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::Path;
+    use std::process::Command;
+    use crate::script::assembly::consts::*;
+    use super::{call_function, Builtins, Variable};
+
+    fn call(builtins: &mut Builtins, id: u32, args: &[Variable]) -> Variable {
+        let mut stack = args.iter().rev().cloned().collect::<Vec<_>>();
+        let mut ret = Variable::Null;
+        call_function(id, &mut stack, &mut ret, builtins);
+        ret
+    }
+
+    fn string(s: &str) -> Variable {
+        Variable::String(s.to_string())
+    }
+
+    fn git(dir: &Path, args: &[&str]) {
+        let status = Command::new("git").arg("-c").arg("init.defaultBranch=main").args(args).current_dir(dir).status().unwrap();
+        assert!(status.success(), "git {} failed", args.join(" "));
+    }
+
+    fn configure(dir: &Path) {
+        git(dir, &["config", "user.name", "Test"]);
+        git(dir, &["config", "user.email", "test@example.com"]);
+        git(dir, &["config", "commit.gpgsign", "false"]);
+    }
+
+    fn strings(value: &Variable) -> Vec<String> {
+        value.list().iter().map(Variable::string).collect()
+    }
+
+    #[test]
+    fn git_builtins() {
+        let root = env::temp_dir().join(format!("mvc-git-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let (remote, work, other) = (root.join("remote.git"), root.join("work"), root.join("other"));
+        fs::create_dir_all(&remote).unwrap();
+        fs::create_dir_all(&work).unwrap();
+        git(&remote, &["init", "-q", "--bare"]);
+        git(&work, &["init", "-q"]);
+        configure(&work);
+        git(&work, &["remote", "add", "origin", remote.to_str().unwrap()]);
+
+        let mut builtins = Builtins {
+            repository: Some(work.clone()),
+            ..Default::default()
+        };
+
+        fs::write(work.join("a.txt"), "one\n").unwrap();
+        assert_eq!(call(&mut builtins, GIT_ADD_ALL, &[]), Variable::Bool(true));
+        assert_eq!(call(&mut builtins, GIT_COMMIT, &[string("first")]), Variable::Bool(true));
+        assert_eq!(call(&mut builtins, GIT_PUSH, &[string("origin main")]), Variable::Bool(true));
+        assert_eq!(call(&mut builtins, GIT_BRANCH, &[]), string("main"));
+        assert_eq!(call(&mut builtins, GIT_STATUS, &[]), Variable::List(vec![]));
+        assert_eq!(call(&mut builtins, GIT_DIFF_STATS, &[]), Variable::List(vec![Variable::Int(0); 3]));
+
+        fs::write(work.join("a.txt"), "two\nthree\n").unwrap();
+        fs::write(work.join("b.txt"), "new\n").unwrap();
+        assert_eq!(strings(&call(&mut builtins, GIT_STATUS, &[])), ["a.txt", "b.txt"]);
+        assert_eq!(call(&mut builtins, GIT_DIFF_STATS, &[]), Variable::List(vec![Variable::Int(1), Variable::Int(2), Variable::Int(1)]));
+
+        assert_eq!(call(&mut builtins, GIT_TAG, &[string("v1")]), Variable::Bool(true));
+        assert_eq!(call(&mut builtins, GIT_TAG, &[string("v1")]), Variable::Bool(false));
+        assert!(builtins.error.string().contains("already exists"));
+
+        assert_eq!(call(&mut builtins, GIT_ADD, &[string("a.txt")]), Variable::Bool(true));
+        assert_eq!(call(&mut builtins, GIT_COMMIT, &[string("second")]), Variable::Bool(true));
+        assert_eq!(call(&mut builtins, GIT_PUSH_UPSTREAM, &[]), Variable::Bool(true));
+        assert_eq!(builtins.error, Variable::Null);
+        let log = strings(&call(&mut builtins, GIT_LOG, &[Variable::Int(5)]));
+        assert_eq!(log.len(), 2);
+        assert!(log[0].ends_with(" second") && log[1].ends_with(" first"));
+        assert_eq!(strings(&call(&mut builtins, GIT_STATUS, &[])), ["b.txt"]);
+
+        assert_eq!(call(&mut builtins, GIT_CHECKOUT, &[string("v1")]), Variable::Bool(true));
+        assert_eq!(call(&mut builtins, GIT_BRANCH, &[]), string("HEAD"));
+        assert_eq!(fs::read_to_string(work.join("a.txt")).unwrap(), "one\n");
+        assert_eq!(call(&mut builtins, GIT_CHECKOUT, &[string("missing")]), Variable::Bool(false));
+        assert!(builtins.error.string().contains("missing"));
+        assert_eq!(call(&mut builtins, GIT_CHECKOUT, &[string("main")]), Variable::Bool(true));
+
+        git(&root, &["clone", "-q", remote.to_str().unwrap(), other.to_str().unwrap()]);
+        configure(&other);
+        fs::write(other.join("c.txt"), "remote\n").unwrap();
+        git(&other, &["add", "c.txt"]);
+        git(&other, &["commit", "-q", "-m", "third"]);
+        git(&other, &["push", "-q"]);
+
+        assert_eq!(call(&mut builtins, GIT_FETCH, &[]), Variable::Bool(true));
+        assert!(!work.join("c.txt").exists());
+        assert_eq!(call(&mut builtins, GIT_PULL, &[]), Variable::Bool(true));
+        assert_eq!(fs::read_to_string(work.join("c.txt")).unwrap(), "remote\n");
+        assert!(strings(&call(&mut builtins, GIT_LOG, &[Variable::Int(1)]))[0].ends_with(" third"));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}